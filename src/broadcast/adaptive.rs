@@ -0,0 +1,219 @@
+//! Tuning the gossip interval and batch size to how far behind the neighbours are.
+//!
+//! Gossiping often delivers messages sooner, while gossiping rarely lets each gossip carry more
+//! of them, so the interval shrinks whenever a neighbour has been waiting long enough to threaten
+//! the latency target and grows while rounds only have a few messages to send.
+//!
+//! Likewise, a neighbour is only gossiped to once it is missing a batch of ids, or once it has
+//! waited so long that the gossip would otherwise arrive late. The batch grows while neighbours
+//! are on time and halves when one falls behind. How late gossip arrives is estimated from the
+//! round trips of gossip that is replied to.
+
+use std::time::Duration;
+
+use crate::config::AdaptiveConfig;
+
+/// How much faster to gossip when a neighbour is falling behind.
+const SPEED_UP: f64 = 0.5;
+/// How much slower to gossip when there is little to send.
+const SLOW_DOWN: f64 = 1.25;
+/// How much weight a new round trip carries in the reply latency.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveInterval {
+    config: AdaptiveConfig,
+    interval: Duration,
+    /// How many ids a neighbour must be missing before it is gossiped to on time.
+    batch: usize,
+    /// A moving average of the round trips of gossip.
+    reply_latency: Option<Duration>,
+}
+
+/// What happened in a round of gossip.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Round {
+    /// How long the neighbour that has been missing messages the longest has been missing them.
+    pub oldest_pending: Option<Duration>,
+    pub messages: usize,
+    pub ids: usize,
+}
+
+impl AdaptiveInterval {
+    pub fn new(mut config: AdaptiveConfig, interval: Duration) -> Self {
+        // Bounds given the wrong way round would make every clamp panic.
+        if config.min_gossip_interval > config.max_gossip_interval {
+            std::mem::swap(
+                &mut config.min_gossip_interval,
+                &mut config.max_gossip_interval,
+            );
+        }
+        let interval = interval.clamp(config.min_gossip_interval, config.max_gossip_interval);
+        Self {
+            config,
+            interval,
+            batch: 1,
+            reply_latency: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn latency_target(&self) -> Duration {
+        self.config.latency_target
+    }
+
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    pub fn reply_latency(&self) -> Option<Duration> {
+        self.reply_latency
+    }
+
+    /// Whether a neighbour that has been missing messages for `wait` would get them too late if
+    /// it waited another round.
+    pub fn is_urgent(&self, wait: Duration) -> bool {
+        let one_way = self.reply_latency.unwrap_or_default() / 2;
+        wait + self.interval + one_way > self.config.latency_target
+    }
+
+    /// Record how long a neighbour took to reply to gossip.
+    pub fn observe_reply(&mut self, round_trip: Duration) {
+        self.reply_latency = Some(match self.reply_latency {
+            Some(latency) => {
+                latency.mul_f64(1.0 - LATENCY_WEIGHT) + round_trip.mul_f64(LATENCY_WEIGHT)
+            }
+            None => round_trip,
+        });
+    }
+
+    pub fn observe(&mut self, round: Round) {
+        let is_falling_behind = round
+            .oldest_pending
+            .is_some_and(|wait| self.is_urgent(wait));
+        self.batch = match is_falling_behind {
+            true => self.batch / 2,
+            false => self.batch + 1,
+        }
+        .clamp(1, self.config.max_batch.max(1));
+        let is_underfilled =
+            round.messages == 0 || round.ids < round.messages * self.config.min_batch;
+        let factor = match (is_falling_behind, is_underfilled) {
+            (true, _) => SPEED_UP,
+            (false, true) => SLOW_DOWN,
+            (false, false) => return,
+        };
+        self.interval = self.interval.mul_f64(factor).clamp(
+            self.config.min_gossip_interval,
+            self.config.max_gossip_interval,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adaptive() -> AdaptiveInterval {
+        let config = AdaptiveConfig {
+            adaptive: true,
+            min_gossip_interval: Duration::from_millis(20),
+            max_gossip_interval: Duration::from_millis(800),
+            latency_target: Duration::from_millis(400),
+            min_batch: 4,
+            max_batch: 8,
+        };
+        AdaptiveInterval::new(config, Duration::from_millis(100))
+    }
+
+    #[test]
+    fn test_speeds_up_when_neighbours_fall_behind() {
+        let mut adaptive = adaptive();
+        let round = Round {
+            oldest_pending: Some(Duration::from_millis(390)),
+            messages: 3,
+            ids: 30,
+        };
+        adaptive.observe(round);
+        assert_eq!(adaptive.interval(), Duration::from_millis(50));
+        for _ in 0..5 {
+            adaptive.observe(round);
+        }
+        assert_eq!(adaptive.interval(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_slows_down_while_rounds_are_underfilled() {
+        let mut adaptive = adaptive();
+        adaptive.observe(Round::default());
+        assert_eq!(adaptive.interval(), Duration::from_millis(125));
+        let round = Round {
+            oldest_pending: None,
+            messages: 2,
+            ids: 3,
+        };
+        for _ in 0..20 {
+            adaptive.observe(round);
+        }
+        assert_eq!(adaptive.interval(), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_holds_steady_when_batches_are_full_and_on_time() {
+        let mut adaptive = adaptive();
+        adaptive.observe(Round {
+            oldest_pending: Some(Duration::from_millis(10)),
+            messages: 2,
+            ids: 8,
+        });
+        assert_eq!(adaptive.interval(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_batch_grows_while_on_time_and_halves_when_behind() {
+        let mut adaptive = adaptive();
+        assert_eq!(adaptive.batch(), 1);
+        for _ in 0..10 {
+            adaptive.observe(Round::default());
+        }
+        assert_eq!(adaptive.batch(), 8);
+        adaptive.observe(Round {
+            oldest_pending: Some(Duration::from_secs(1)),
+            messages: 1,
+            ids: 1,
+        });
+        assert_eq!(adaptive.batch(), 4);
+    }
+
+    #[test]
+    fn test_reply_latency_brings_urgency_forward() {
+        let mut adaptive = adaptive();
+        let wait = Duration::from_millis(250);
+        assert!(!adaptive.is_urgent(wait));
+        adaptive.observe_reply(Duration::from_millis(200));
+        assert_eq!(adaptive.reply_latency(), Some(Duration::from_millis(200)));
+        assert!(adaptive.is_urgent(wait));
+        adaptive.observe_reply(Duration::from_millis(100));
+        assert_eq!(adaptive.reply_latency(), Some(Duration::from_millis(180)));
+    }
+
+    #[test]
+    fn test_bounds_given_the_wrong_way_round_are_swapped() {
+        let config = AdaptiveConfig {
+            min_gossip_interval: Duration::from_millis(800),
+            max_gossip_interval: Duration::from_millis(20),
+            ..adaptive().config
+        };
+        let mut adaptive = AdaptiveInterval::new(config, Duration::from_secs(5));
+        assert_eq!(adaptive.interval(), Duration::from_millis(800));
+        adaptive.observe(Round {
+            oldest_pending: Some(Duration::from_millis(390)),
+            messages: 3,
+            ids: 3,
+        });
+        assert_eq!(adaptive.interval(), Duration::from_millis(400));
+    }
+}
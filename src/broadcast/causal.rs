@@ -0,0 +1,236 @@
+//! Causal delivery of broadcasts.
+//!
+//! Each node stamps the messages broadcast to it with its own id, a sequence number, and a
+//! vector clock of how many messages from each origin it had delivered at the time. A message
+//! is delivered once every earlier message from its origin and everything its clock depends on
+//! has been, so anything read before it at another node is read before it here too.
+//!
+//! Stamps travel alongside the ids they are for, however those ids are disseminated. The same
+//! value broadcast to two nodes gets a stamp from each, and a peer that already holds the id
+//! would never be sent the later stamp with it. So gossip also carries the sender's delivered
+//! clock, and each gossip to a peer carries every stamp past the clock that peer last reported,
+//! whether or not the peer holds the ids they are for.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use gossip_glomers::message::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::id_set::IdSet;
+
+/// How many messages from each origin have been delivered.
+pub type VectorClock = BTreeMap<NodeId, usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Stamp {
+    pub id: usize,
+    pub origin: NodeId,
+    /// Counts from 1 for each origin.
+    pub seq: usize,
+    /// The origin's delivered clock when it stamped the message.
+    #[serde(default, skip_serializing_if = "VectorClock::is_empty")]
+    pub deps: VectorClock,
+}
+
+#[derive(Debug, Default)]
+pub struct Causal {
+    delivered: VectorClock,
+    /// Every stamp known, by origin and sequence number.
+    stamps: HashMap<NodeId, BTreeMap<usize, Stamp>>,
+    /// The origin and sequence number of every stamp known, by the id it is for.
+    keys: HashMap<usize, Vec<(NodeId, usize)>>,
+    /// The delivered clock each peer last reported.
+    peer_clocks: HashMap<NodeId, VectorClock>,
+    /// Stamps waiting on their dependencies, by origin and sequence number.
+    pending: BTreeMap<(NodeId, usize), Stamp>,
+    /// The ids delivered, in the order they were.
+    order: Vec<usize>,
+    delivered_ids: HashSet<usize>,
+}
+
+impl Causal {
+    /// Stamp and deliver `id`, broadcast to this node, `origin`.
+    pub fn stamp(&mut self, origin: &NodeId, id: usize) -> Stamp {
+        let seq = self.delivered.get(origin).copied().unwrap_or_default() + 1;
+        let stamp = Stamp {
+            id,
+            origin: origin.clone(),
+            seq,
+            deps: self.delivered.clone(),
+        };
+        self.receive([stamp.clone()]);
+        stamp
+    }
+
+    /// Take in stamps from a peer, returning the ids that this delivers for the first time.
+    pub fn receive(&mut self, stamps: impl IntoIterator<Item = Stamp>) -> Vec<usize> {
+        for stamp in stamps {
+            let delivered = self
+                .delivered
+                .get(&stamp.origin)
+                .copied()
+                .unwrap_or_default();
+            let key = (stamp.origin.clone(), stamp.seq);
+            if stamp.seq <= delivered || self.pending.contains_key(&key) {
+                continue;
+            }
+            self.keys.entry(stamp.id).or_default().push(key.clone());
+            let by_seq = self.stamps.entry(stamp.origin.clone()).or_default();
+            by_seq.insert(stamp.seq, stamp.clone());
+            self.pending.insert(key, stamp);
+        }
+        let mut newly_delivered = Vec::new();
+        while let Some(key) = self.next_deliverable() {
+            let stamp = self
+                .pending
+                .remove(&key)
+                .expect("deliverable stamps are pending");
+            *self.delivered.entry(stamp.origin).or_default() = stamp.seq;
+            if self.delivered_ids.insert(stamp.id) {
+                self.order.push(stamp.id);
+                newly_delivered.push(stamp.id);
+            }
+        }
+        newly_delivered
+    }
+
+    fn next_deliverable(&self) -> Option<(NodeId, usize)> {
+        self.pending
+            .iter()
+            .find(|((origin, seq), stamp)| {
+                let delivered =
+                    |node: &NodeId| self.delivered.get(node).copied().unwrap_or_default();
+                *seq == delivered(origin) + 1
+                    && stamp
+                        .deps
+                        .iter()
+                        .all(|(node, count)| node == origin || delivered(node) >= *count)
+            })
+            .map(|(key, _)| key.clone())
+    }
+
+    /// The ids delivered, in the order they were.
+    pub fn delivered(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// How many messages from each origin have been delivered.
+    pub fn clock(&self) -> &VectorClock {
+        &self.delivered
+    }
+
+    /// Every stamp known for `ids`, to send along with them.
+    pub fn stamps_for(&self, ids: &IdSet) -> Vec<Stamp> {
+        ids.iter()
+            .filter_map(|id| self.keys.get(&id))
+            .flatten()
+            .filter_map(|(origin, seq)| self.stamps.get(origin)?.get(seq))
+            .cloned()
+            .collect()
+    }
+
+    /// Record the delivered clock that `peer` reported.
+    pub fn observe_clock(&mut self, peer: NodeId, clock: VectorClock) {
+        let known = self.peer_clocks.entry(peer).or_default();
+        for (origin, count) in clock {
+            let known = known.entry(origin).or_default();
+            *known = (*known).max(count);
+        }
+    }
+
+    /// Every stamp known past the clock that `peer` last reported, which it may be missing.
+    pub fn stamps_for_peer(&self, peer: &NodeId) -> Vec<Stamp> {
+        let clock = self.peer_clocks.get(peer);
+        self.stamps
+            .iter()
+            .flat_map(|(origin, by_seq)| {
+                let delivered = clock
+                    .and_then(|clock| clock.get(origin))
+                    .copied()
+                    .unwrap_or_default();
+                by_seq.range(delivered + 1..).map(|(_, stamp)| stamp)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(number: usize) -> NodeId {
+        NodeId::new(number)
+    }
+
+    #[test]
+    fn test_stamp_delivers_and_counts_per_origin() {
+        let mut causal = Causal::default();
+        let first = causal.stamp(&node(0), 10);
+        let second = causal.stamp(&node(0), 11);
+        assert_eq!((first.seq, second.seq), (1, 2));
+        assert_eq!(second.deps, VectorClock::from([(node(0), 1)]));
+        assert_eq!(causal.delivered(), [10, 11]);
+    }
+
+    #[test]
+    fn test_receive_waits_for_dependencies() {
+        let mut origin = Causal::default();
+        let read = origin.stamp(&node(1), 1);
+        let mut replier = Causal::default();
+        replier.receive([read.clone()]);
+        let reply = replier.stamp(&node(2), 2);
+
+        let mut causal = Causal::default();
+        assert!(causal.receive([reply]).is_empty());
+        assert!(causal.delivered().is_empty());
+        assert_eq!(causal.receive([read]), [1, 2]);
+        assert_eq!(causal.delivered(), [1, 2]);
+    }
+
+    #[test]
+    fn test_receive_waits_for_earlier_messages_from_origin() {
+        let mut origin = Causal::default();
+        let first = origin.stamp(&node(1), 1);
+        let second = origin.stamp(&node(1), 2);
+
+        let mut causal = Causal::default();
+        assert!(causal.receive([second.clone()]).is_empty());
+        assert_eq!(causal.receive([first.clone(), second]), [1, 2]);
+        assert!(causal.receive([first]).is_empty());
+    }
+
+    #[test]
+    fn test_same_id_from_two_origins_is_delivered_once() {
+        let mut a = Causal::default();
+        let mut b = Causal::default();
+        let from_a = a.stamp(&node(1), 5);
+        let from_b = b.stamp(&node(2), 5);
+        let next_from_b = b.stamp(&node(2), 6);
+
+        let mut causal = Causal::default();
+        causal.receive([from_a]);
+        assert!(causal.receive([next_from_b]).is_empty());
+        assert_eq!(causal.receive([from_b]), [6]);
+        assert_eq!(causal.delivered(), [5, 6]);
+        assert_eq!(causal.stamps_for(&IdSet::from_iter([5])).len(), 2);
+    }
+
+    #[test]
+    fn test_peers_are_sent_stamps_past_their_clock() {
+        let mut causal = Causal::default();
+        causal.stamp(&node(1), 5);
+        let second = causal.stamp(&node(1), 6);
+        causal.stamp(&node(2), 5);
+        assert_eq!(causal.stamps_for_peer(&node(3)).len(), 3);
+
+        causal.observe_clock(node(3), VectorClock::from([(node(1), 1), (node(2), 1)]));
+        assert_eq!(
+            causal.stamps_for_peer(&node(3)),
+            [second.clone()].as_slice()
+        );
+        causal.observe_clock(node(3), VectorClock::from([(node(1), 0)]));
+        assert_eq!(causal.stamps_for_peer(&node(3)), [second]);
+        assert_eq!(causal.stamps_for_peer(&node(4)).len(), 3);
+    }
+}
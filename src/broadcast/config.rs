@@ -0,0 +1,215 @@
+use std::{collections::HashSet, num::NonZeroUsize, time::Duration};
+
+use clap::{Args, ValueEnum};
+use gossip_glomers::{
+    config::{parse_duration, GossipConfig, MembershipConfig},
+    message::NodeId,
+};
+
+use crate::{
+    id_generator::IdGenerator,
+    topology::{self, Topology, TopologyStrategy},
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct BroadcastConfig {
+    #[command(flatten)]
+    pub gossip: GossipConfig,
+    #[command(flatten)]
+    pub adaptive: AdaptiveConfig,
+    /// Detect failed peers and gossip with other nodes in place of neighbours that are dead.
+    #[arg(long = "membership", env = "GOSSIP_GLOMERS_MEMBERSHIP")]
+    pub use_membership: bool,
+    #[command(flatten)]
+    pub membership: MembershipConfig,
+    #[command(flatten)]
+    pub topology: TopologyConfig,
+    /// Only show a message to `read` once every message read before it at its origin has been.
+    #[arg(long, env = "GOSSIP_GLOMERS_CAUSAL")]
+    pub causal: bool,
+    /// How new messages spread to the other nodes.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_DISSEMINATION",
+        value_enum,
+        default_value_t = Dissemination::Gossip
+    )]
+    pub dissemination: Dissemination,
+    /// How long a `plumtree` node waits for a message it has heard of before asking for it.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_GRAFT_TIMEOUT",
+        default_value = "300ms",
+        value_parser = parse_duration
+    )]
+    pub graft_timeout: Duration,
+    /// How long an `acked` node waits for a neighbour to acknowledge a message before sending it
+    /// again, doubling with each attempt.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_FORWARD_TIMEOUT",
+        default_value = "200ms",
+        value_parser = parse_duration
+    )]
+    pub forward_timeout: Duration,
+    /// The longest an `acked` node waits between attempts.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_MAX_FORWARD_TIMEOUT",
+        default_value = "2s",
+        value_parser = parse_duration
+    )]
+    pub max_forward_timeout: Duration,
+    /// How many times a `total-order` node sends a `lin-kv` request again, doubling its timeout
+    /// each time, before failing the broadcast it is for.
+    #[arg(long, env = "GOSSIP_GLOMERS_KV_RETRIES", default_value_t = 3)]
+    pub kv_retries: u32,
+    /// How many random nodes a `push-pull` node exchanges messages with each gossip interval.
+    #[arg(long, env = "GOSSIP_GLOMERS_PUSH_PULL_FANOUT", default_value_t = 3)]
+    pub push_pull_fanout: usize,
+    /// Seeds the choice of `push-pull` partners, offset by the node number so that nodes differ.
+    #[arg(long, env = "GOSSIP_GLOMERS_PUSH_PULL_SEED", default_value_t = 0)]
+    pub push_pull_seed: u64,
+    /// How nodes find and repair differences between the messages they have seen.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_ANTI_ENTROPY",
+        value_enum,
+        default_value_t = AntiEntropy::None
+    )]
+    pub anti_entropy: AntiEntropy,
+    /// Run anti-entropy once every this many gossip intervals.
+    #[arg(long, env = "GOSSIP_GLOMERS_ANTI_ENTROPY_ROUNDS", default_value = "10")]
+    pub anti_entropy_rounds: NonZeroUsize,
+    /// Track which messages every node has, and stop gossiping them and remembering them per
+    /// neighbour.
+    #[arg(long, env = "GOSSIP_GLOMERS_GC")]
+    pub gc: bool,
+    /// Tell every other node which messages this one has that are not yet stable once every
+    /// this many gossip intervals.
+    #[arg(long, env = "GOSSIP_GLOMERS_GC_ROUNDS", default_value = "10")]
+    pub gc_rounds: NonZeroUsize,
+    /// What kind of ids `generate` returns.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_ID_GENERATOR",
+        value_enum,
+        default_value_t = IdGenerator::Uuid
+    )]
+    pub id_generator: IdGenerator,
+    /// How far back the clock may jump before `snowflake` ids fail rather than wait for it.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_MAX_CLOCK_REGRESSION",
+        default_value = "10ms",
+        value_parser = parse_duration
+    )]
+    pub max_clock_regression: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dissemination {
+    /// Periodically send each neighbour the messages it is not known to have.
+    Gossip,
+    /// Push along a self-repairing spanning tree and announce to the other neighbours.
+    Plumtree,
+    /// Forward each message to every neighbour and retry until they acknowledge it.
+    Acked,
+    /// Ignore the topology and swap messages both ways with random nodes every gossip interval.
+    PushPull,
+    /// Agree on the order of messages through Maelstrom's `lin-kv`, and read them in it.
+    TotalOrder,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct AdaptiveConfig {
+    /// Tune the gossip interval to how far behind the neighbours are, starting from
+    /// `--gossip-interval`, and jitter it instead of staggering nodes by number.
+    #[arg(long, env = "GOSSIP_GLOMERS_ADAPTIVE")]
+    pub adaptive: bool,
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_MIN_GOSSIP_INTERVAL",
+        default_value = "20ms",
+        value_parser = parse_duration
+    )]
+    pub min_gossip_interval: Duration,
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_MAX_GOSSIP_INTERVAL",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    pub max_gossip_interval: Duration,
+    /// How long a neighbour may go without a message before gossip speeds up.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_LATENCY_TARGET",
+        default_value = "400ms",
+        value_parser = parse_duration
+    )]
+    pub latency_target: Duration,
+    /// Gossip slows down while its messages carry fewer ids than this on average.
+    #[arg(long, env = "GOSSIP_GLOMERS_MIN_BATCH", default_value_t = 4)]
+    pub min_batch: usize,
+    /// The most ids a neighbour waits to be missing before it is gossiped to, unless it has
+    /// waited long enough to threaten the latency target.
+    #[arg(long, env = "GOSSIP_GLOMERS_MAX_BATCH", default_value_t = 32)]
+    pub max_batch: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AntiEntropy {
+    /// Rely on dissemination alone.
+    None,
+    /// Trade a count and hash of the messages seen, and exchange messages only if they differ.
+    Digest,
+    /// Compare Merkle trees of the messages seen, descending only into subtrees that differ.
+    Merkle,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TopologyConfig {
+    /// Which neighbours each node gossips with.
+    #[arg(
+        long = "topology",
+        env = "GOSSIP_GLOMERS_TOPOLOGY",
+        value_enum,
+        default_value_t = TopologyStrategy::Hub
+    )]
+    pub strategy: TopologyStrategy,
+    /// How many children each node has in the `tree` topology.
+    #[arg(long, env = "GOSSIP_GLOMERS_TREE_ARITY", default_value_t = 2)]
+    pub tree_arity: usize,
+    /// How many neighbours each node has in the `random-regular` topology.
+    #[arg(long, env = "GOSSIP_GLOMERS_DEGREE", default_value_t = 3)]
+    pub degree: usize,
+    /// Seeds the `random-regular` topology, which must be the same on every node.
+    #[arg(long, env = "GOSSIP_GLOMERS_TOPOLOGY_SEED", default_value_t = 0)]
+    pub topology_seed: u64,
+}
+
+impl TopologyConfig {
+    /// The neighbours of `node_id`, given every node and the topology Maelstrom suggested.
+    pub fn neighbours(
+        &self,
+        node_id: &NodeId,
+        node_ids: &HashSet<NodeId>,
+        mut suggested: Topology,
+    ) -> HashSet<NodeId> {
+        let mut node_ids: Vec<_> = node_ids.iter().cloned().collect();
+        node_ids.sort();
+        let mut topology = match self.strategy {
+            TopologyStrategy::Maelstrom => return suggested.remove(node_id).unwrap_or_default(),
+            TopologyStrategy::Hub => topology::hub(&node_ids),
+            TopologyStrategy::Ring => topology::ring(&node_ids),
+            TopologyStrategy::Grid => topology::grid(&node_ids),
+            TopologyStrategy::Tree => topology::tree(&node_ids, self.tree_arity),
+            TopologyStrategy::RandomRegular => {
+                topology::random_regular(&node_ids, self.degree, self.topology_seed)
+            }
+            TopologyStrategy::FullMesh => topology::full_mesh(&node_ids),
+        };
+        topology.remove(node_id).unwrap_or_default()
+    }
+}
@@ -0,0 +1,177 @@
+//! Unique ids for `generate` requests.
+//!
+//! Snowflake ids, after Twitter's, pack from the top 41 bits of milliseconds since [`EPOCH`],
+//! 10 bits of node number and 12 bits of sequence within the millisecond. They fit in 64 bits,
+//! sort by when they were generated, and need no coordination, since no two nodes share a
+//! number and no node reuses a millisecond and sequence.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The start of 2020, in milliseconds since the Unix epoch.
+pub const EPOCH: u64 = 1_577_836_800_000;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IdGenerator {
+    /// Random 128-bit UUIDs.
+    Uuid,
+    /// Time-sortable 64-bit ids.
+    Snowflake,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum GeneratedId {
+    Snowflake(u64),
+    Uuid(Uuid),
+}
+
+/// Why a Snowflake id could not be generated at the time asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailable {
+    /// Not until this millisecond, because the sequence is used up or the clock went back.
+    Until(u64),
+    /// The clock went back by this many milliseconds, which is more than is worth waiting.
+    ClockRegressed(u64),
+}
+
+#[derive(Debug)]
+pub struct Snowflake {
+    node_number: u64,
+    /// How far the clock may go back before generating fails rather than waits.
+    max_clock_regression: Duration,
+    /// The millisecond of the last id generated, since [`EPOCH`].
+    last_millis: u64,
+    sequence: u64,
+}
+
+impl Snowflake {
+    /// A generator for `node_number`, unless it does not fit in an id.
+    pub fn new(node_number: usize, max_clock_regression: Duration) -> Option<Self> {
+        let node_number = u64::try_from(node_number).ok()?;
+        (node_number < 1 << NODE_BITS).then_some(Self {
+            node_number,
+            max_clock_regression,
+            last_millis: 0,
+            sequence: 0,
+        })
+    }
+
+    /// The next id at `now`, in milliseconds since the Unix epoch.
+    pub fn generate(&mut self, now: u64) -> Result<u64, Unavailable> {
+        let millis = now.saturating_sub(EPOCH);
+        if millis < self.last_millis {
+            let regression = self.last_millis - millis;
+            if u128::from(regression) > self.max_clock_regression.as_millis() {
+                return Err(Unavailable::ClockRegressed(regression));
+            }
+            return Err(Unavailable::Until(self.last_millis + EPOCH));
+        }
+        if millis == self.last_millis {
+            if self.sequence + 1 == 1 << SEQUENCE_BITS {
+                return Err(Unavailable::Until(self.last_millis + 1 + EPOCH));
+            }
+            self.sequence += 1;
+        } else {
+            self.last_millis = millis;
+            self.sequence = 0;
+        }
+        let id = millis << (NODE_BITS + SEQUENCE_BITS)
+            | self.node_number << SEQUENCE_BITS
+            | self.sequence;
+        Ok(id)
+    }
+
+    /// The next id, waiting for the clock if need be.
+    pub fn next_id(&mut self) -> Result<u64, Unavailable> {
+        loop {
+            let now = now_millis();
+            match self.generate(now) {
+                Err(Unavailable::Until(until)) => {
+                    thread::sleep(Duration::from_millis(until.saturating_sub(now)));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snowflake(node_number: usize) -> Snowflake {
+        Snowflake::new(node_number, Duration::from_millis(5)).expect("The node number fits")
+    }
+
+    #[test]
+    fn test_ids_pack_time_node_and_sequence() {
+        let mut snowflake = snowflake(3);
+        let id = snowflake.generate(EPOCH + 2).expect("An id is available");
+        assert_eq!(id, 2 << 22 | 3 << 12);
+        let next = snowflake.generate(EPOCH + 2).expect("An id is available");
+        assert_eq!(next, id + 1);
+        let later = snowflake.generate(EPOCH + 3).expect("An id is available");
+        assert_eq!(later, 3 << 22 | 3 << 12);
+    }
+
+    #[test]
+    fn test_ids_from_different_nodes_differ() {
+        let a = snowflake(1).generate(EPOCH + 9);
+        let b = snowflake(2).generate(EPOCH + 9);
+        assert_ne!(a, b);
+        assert!(Snowflake::new(1024, Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn test_used_up_sequence_waits_for_next_millisecond() {
+        let mut snowflake = snowflake(0);
+        for _ in 0..4096 {
+            snowflake.generate(EPOCH + 7).expect("An id is available");
+        }
+        assert_eq!(
+            snowflake.generate(EPOCH + 7),
+            Err(Unavailable::Until(EPOCH + 8))
+        );
+        assert!(snowflake.generate(EPOCH + 8).is_ok());
+    }
+
+    #[test]
+    fn test_clock_regression_waits_or_fails() {
+        let mut snowflake = snowflake(0);
+        let id = snowflake.generate(EPOCH + 100).expect("An id is available");
+        assert_eq!(
+            snowflake.generate(EPOCH + 97),
+            Err(Unavailable::Until(EPOCH + 100))
+        );
+        assert_eq!(
+            snowflake.generate(EPOCH + 90),
+            Err(Unavailable::ClockRegressed(10))
+        );
+        assert!(snowflake.generate(EPOCH + 100).expect("An id is available") > id);
+    }
+
+    #[test]
+    fn test_generated_ids_serialize_as_numbers_or_strings() {
+        let json = serde_json::to_string(&GeneratedId::Snowflake(42)).expect("Serializable");
+        assert_eq!(json, "42");
+        let id = Uuid::nil();
+        let json = serde_json::to_string(&GeneratedId::Uuid(id)).expect("Serializable");
+        assert_eq!(json, format!("\"{id}\""));
+    }
+}
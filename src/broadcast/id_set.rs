@@ -0,0 +1,254 @@
+//! Sets of broadcast ids stored as sorted, disjoint ranges.
+//!
+//! Maelstrom hands out broadcast values that are mostly dense runs of integers, so a set of
+//! ranges stays small however many ids it holds and set operations cost time in the number of
+//! ranges rather than the number of ids.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::{Bound, Range},
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The largest id a set can hold, since each range is stored with an exclusive end.
+pub const MAX_ID: usize = usize::MAX - 1;
+
+/// Serializes as a list of inclusive `[first, last]` pairs, e.g. `[[0,4],[7,7]]`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct IdSet {
+    /// The start of each range mapped to its exclusive end.
+    ranges: BTreeMap<usize, usize>,
+}
+
+impl IdSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+
+    /// A summary that equals another set's digest only if, barring hash collisions, the sets
+    /// are equal.
+    pub fn digest(&self) -> Digest {
+        let mut hasher = DefaultHasher::new();
+        self.ranges.hash(&mut hasher);
+        Digest {
+            count: self.len(),
+            hash: hasher.finish(),
+        }
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.ranges
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, &end)| id < end)
+    }
+
+    /// Add `id`, returning whether it was new. Ids above [`MAX_ID`] are ignored.
+    pub fn insert(&mut self, id: usize) -> bool {
+        if id > MAX_ID {
+            return false;
+        }
+        let is_new = !self.contains(id);
+        self.insert_range(id..id + 1);
+        is_new
+    }
+
+    pub fn insert_range(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let Range { mut start, mut end } = range;
+        if let Some((&previous_start, &previous_end)) = self.ranges.range(..=start).next_back() {
+            if previous_end >= start {
+                start = previous_start;
+                end = end.max(previous_end);
+            }
+        }
+        let overlapping: Vec<_> = self
+            .ranges
+            .range(start..=end)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+        for (overlapping_start, overlapping_end) in overlapping {
+            self.ranges.remove(&overlapping_start);
+            end = end.max(overlapping_end);
+        }
+        self.ranges.insert(start, end);
+    }
+
+    pub fn union_with(&mut self, other: &IdSet) {
+        for range in other.ranges() {
+            self.insert_range(range);
+        }
+    }
+
+    /// The ids in `self` that are not in `other`.
+    pub fn difference(&self, other: &IdSet) -> IdSet {
+        let mut difference = IdSet::new();
+        for Range { start, end } in self.ranges() {
+            let mut cursor = start;
+            let preceding = other.ranges.range(..=start).next_back();
+            let within = other
+                .ranges
+                .range((Bound::Excluded(start), Bound::Excluded(end)));
+            for (&other_start, &other_end) in preceding.into_iter().chain(within) {
+                if other_start > cursor {
+                    difference.ranges.insert(cursor, other_start);
+                }
+                cursor = cursor.max(other_end);
+            }
+            if cursor < end {
+                difference.ranges.insert(cursor, end);
+            }
+        }
+        difference
+    }
+
+    /// The ids in both `self` and `other`.
+    pub fn intersection(&self, other: &IdSet) -> IdSet {
+        self.difference(&self.difference(other))
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.ranges.iter().map(|(&start, &end)| start..end)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ranges().flatten()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Digest {
+    pub count: usize,
+    pub hash: u64,
+}
+
+impl fmt::Debug for IdSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.ranges()).finish()
+    }
+}
+
+impl FromIterator<usize> for IdSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(ids: I) -> Self {
+        let mut set = IdSet::new();
+        set.extend(ids);
+        set
+    }
+}
+
+impl Extend<usize> for IdSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, ids: I) {
+        for id in ids {
+            self.insert(id);
+        }
+    }
+}
+
+impl Serialize for IdSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.ranges().map(|range| (range.start, range.end - 1)))
+    }
+}
+
+impl<'de> Deserialize<'de> for IdSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs = Vec::<(usize, usize)>::deserialize(deserializer)?;
+        let mut set = IdSet::new();
+        for (first, last) in pairs {
+            if first > last {
+                let text = format!("range [{first}, {last}] ends before it starts");
+                return Err(de::Error::custom(text));
+            }
+            if last > MAX_ID {
+                let text = format!("range [{first}, {last}] ends after the largest id, {MAX_ID}");
+                return Err(de::Error::custom(text));
+            }
+            set.insert_range(first..last + 1);
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(set: &IdSet) -> Vec<Range<usize>> {
+        set.ranges().collect()
+    }
+
+    #[test]
+    fn test_insert_merges_adjacent_ranges() {
+        let mut set: IdSet = [0, 1, 2, 5, 7].into_iter().collect();
+        assert_eq!(ranges(&set), [0..3, 5..6, 7..8]);
+        assert!(set.insert(6));
+        assert!(!set.insert(6));
+        assert_eq!(ranges(&set), [0..3, 5..8]);
+        set.insert_range(2..6);
+        assert_eq!(set, (0..8).collect());
+        assert_eq!(set.len(), 8);
+        assert!(set.contains(7));
+        assert!(!set.contains(8));
+    }
+
+    #[test]
+    fn test_difference_and_intersection() {
+        let ours: IdSet = (0..10).chain(20..30).collect();
+        let theirs: IdSet = (3..5).chain(8..22).chain(25..26).collect();
+        let difference = ours.difference(&theirs);
+        assert_eq!(ranges(&difference), [0..3, 5..8, 22..25, 26..30]);
+        assert_eq!(
+            ranges(&ours.intersection(&theirs)),
+            [3..5, 8..10, 20..22, 25..26]
+        );
+        assert!(ours.difference(&ours).is_empty());
+        assert_eq!(ours.difference(&IdSet::new()), ours);
+    }
+
+    #[test]
+    fn test_digest() {
+        let ours: IdSet = (0..10).collect();
+        let mut theirs: IdSet = (5..10).rev().chain(0..5).collect();
+        assert_eq!(ours.digest(), theirs.digest());
+        assert_eq!(ours.digest().count, 10);
+        theirs.insert(20);
+        assert_ne!(ours.digest(), theirs.digest());
+    }
+
+    #[test]
+    fn test_serde() {
+        let set: IdSet = [0, 1, 2, 3, 4, 7].into_iter().collect();
+        let json = serde_json::to_string(&set).expect("Id sets are serializable");
+        assert_eq!(json, "[[0,4],[7,7]]");
+        let parsed: IdSet = serde_json::from_str(&json).expect("Id sets are deserializable");
+        assert_eq!(parsed, set);
+        assert!(serde_json::from_str::<IdSet>("[[3,1]]").is_err());
+    }
+
+    #[test]
+    fn test_ids_past_the_largest_are_rejected() {
+        let mut set = IdSet::new();
+        assert!(!set.insert(usize::MAX));
+        assert!(set.is_empty());
+        assert!(set.insert(MAX_ID));
+        assert!(set.contains(MAX_ID));
+
+        let json = format!("[[5,{}]]", usize::MAX);
+        assert!(serde_json::from_str::<IdSet>(&json).is_err());
+        let json = r#"{"type":"gossip","ids_to_see":[[5,18446744073709551615]]}"#;
+        assert!(serde_json::from_str::<crate::message::Payload>(json).is_err());
+    }
+}
@@ -0,0 +1,22 @@
+mod adaptive;
+mod causal;
+mod config;
+mod id_generator;
+mod id_set;
+mod merkle;
+mod message;
+mod node;
+mod plumtree;
+mod stability;
+mod topology;
+mod total_order;
+mod value;
+mod versions;
+
+use gossip_glomers::runtime;
+
+use crate::node::BroadcastNode;
+
+fn main() -> color_eyre::Result<()> {
+    runtime::run::<BroadcastNode>()
+}
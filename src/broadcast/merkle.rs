@@ -0,0 +1,156 @@
+//! A Merkle tree over broadcast ids, for finding which ids two nodes disagree on by comparing
+//! only the subtrees whose summaries differ.
+//!
+//! Ids fall into a fixed number of buckets by their value modulo [`BUCKETS`], and every node of
+//! the tree summarises the ids below it as a count and the XOR of their hashes. Both can be
+//! updated in place as ids arrive, so the tree never needs rebuilding.
+
+use serde::{Deserialize, Serialize};
+
+use crate::id_set::{Digest, IdSet};
+
+const DEPTH: u32 = 8;
+pub const BUCKETS: usize = 1 << DEPTH;
+
+/// A node of the tree, numbered from the root at 1 with the children of `i` at `2i` and `2i + 1`.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MerkleNode {
+    pub index: usize,
+    #[serde(flatten)]
+    pub digest: Digest,
+}
+
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    digests: Vec<Digest>,
+    buckets: Vec<IdSet>,
+}
+
+/// What to send back to a peer after comparing its nodes with this tree's.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Comparison {
+    /// This tree's children of the internal nodes that differ, for the peer to compare in turn.
+    pub children: Vec<MerkleNode>,
+    /// The buckets of the leaves that differ.
+    pub buckets: Vec<usize>,
+}
+
+impl Default for MerkleTree {
+    fn default() -> Self {
+        let empty = Digest { count: 0, hash: 0 };
+        Self {
+            digests: vec![empty; 2 * BUCKETS],
+            buckets: vec![IdSet::new(); BUCKETS],
+        }
+    }
+}
+
+impl MerkleTree {
+    /// Add `id`, unless the tree already has it.
+    pub fn insert(&mut self, id: usize) {
+        let bucket = id % BUCKETS;
+        if !self.buckets[bucket].insert(id) {
+            return;
+        }
+        let hash = mix(id);
+        let mut index = BUCKETS + bucket;
+        while index > 0 {
+            let digest = &mut self.digests[index];
+            digest.count += 1;
+            digest.hash ^= hash;
+            index /= 2;
+        }
+    }
+
+    pub fn root(&self) -> MerkleNode {
+        self.node(1)
+    }
+
+    fn node(&self, index: usize) -> MerkleNode {
+        let digest = self.digests[index];
+        MerkleNode { index, digest }
+    }
+
+    pub fn compare(&self, nodes: &[MerkleNode]) -> Comparison {
+        let mut comparison = Comparison::default();
+        let differing = nodes
+            .iter()
+            .filter(|node| (1..2 * BUCKETS).contains(&node.index))
+            .filter(|node| self.digests[node.index] != node.digest);
+        for node in differing {
+            match node.index >= BUCKETS {
+                true => comparison.buckets.push(node.index - BUCKETS),
+                false => {
+                    let children = [2 * node.index, 2 * node.index + 1];
+                    comparison
+                        .children
+                        .extend(children.map(|index| self.node(index)));
+                }
+            }
+        }
+        comparison
+    }
+
+    /// The ids in `buckets`.
+    pub fn bucket_ids(&self, buckets: &[usize]) -> IdSet {
+        let mut ids = IdSet::new();
+        for bucket in buckets
+            .iter()
+            .filter_map(|bucket| self.buckets.get(*bucket))
+        {
+            ids.union_with(bucket);
+        }
+        ids
+    }
+}
+
+/// Scramble an id's bits (the SplitMix64 finaliser) so that XORing hashes tells sets apart.
+fn mix(id: usize) -> u64 {
+    let mut z = (id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(ids: impl IntoIterator<Item = usize>) -> MerkleTree {
+        let mut tree = MerkleTree::default();
+        ids.into_iter().for_each(|id| tree.insert(id));
+        tree
+    }
+
+    #[test]
+    fn test_insertion_order_does_not_matter() {
+        let ours = tree(0..1000);
+        let theirs = tree((0..1000).rev());
+        assert_eq!(ours.root(), theirs.root());
+        assert_eq!(ours.root().digest.count, 1000);
+        assert_eq!(ours.compare(&[theirs.root()]), Comparison::default());
+    }
+
+    #[test]
+    fn test_descends_only_into_differing_subtrees() {
+        let ours = tree(0..1000);
+        let theirs = tree((0..1000).filter(|id| *id != 300));
+        // Each side compares the other's nodes in turn, as two nodes reconciling would.
+        let trees = [&ours, &theirs];
+        let mut nodes = vec![theirs.root()];
+        let mut buckets = Vec::new();
+        let mut turn = 0;
+        while !nodes.is_empty() {
+            let comparison = trees[turn % 2].compare(&nodes);
+            assert!(comparison.children.len() <= 2);
+            buckets.extend(comparison.buckets);
+            nodes = comparison.children;
+            turn += 1;
+        }
+        assert_eq!(buckets, [300 % BUCKETS]);
+        let missing = ours
+            .bucket_ids(&buckets)
+            .difference(&theirs.bucket_ids(&buckets));
+        assert_eq!(missing, IdSet::from_iter([300]));
+    }
+}
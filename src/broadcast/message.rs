@@ -0,0 +1,155 @@
+use std::collections::{HashMap, HashSet};
+
+use gossip_glomers::{membership::Update, message::NodeId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    causal::{Stamp, VectorClock},
+    id_generator::GeneratedId,
+    id_set::{Digest, IdSet},
+    merkle::MerkleNode,
+    total_order::LogEntry,
+    value::Values,
+};
+
+pub type Message = gossip_glomers::message::Message<Payload>;
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Payload {
+    Echo {
+        echo: String,
+    },
+    EchoOk {
+        echo: String,
+    },
+    Generate,
+    GenerateOk {
+        id: GeneratedId,
+    },
+    Broadcast {
+        message: Value,
+    },
+    BroadcastOk,
+    Read {
+        /// Only read the messages this node has delivered since this version of it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<usize>,
+    },
+    ReadOk {
+        messages: Vec<Value>,
+        /// The version to read `since` next time, when this read was from a version.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<usize>,
+        /// The messages with their slots in the log, when broadcasts are totally ordered.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        log: Vec<LogEntry>,
+    },
+    Topology {
+        topology: HashMap<NodeId, HashSet<NodeId>>,
+    },
+    TopologyOk,
+    Gossip {
+        ids_to_see: IdSet,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+        /// The sender's delivered clock, when messages are delivered in causal order.
+        #[serde(default, skip_serializing_if = "VectorClock::is_empty")]
+        clock: VectorClock,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<Update>,
+    },
+    GossipOk {
+        ids_to_see: IdSet,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+        /// The sender's delivered clock, when messages are delivered in causal order.
+        #[serde(default, skip_serializing_if = "VectorClock::is_empty")]
+        clock: VectorClock,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<Update>,
+    },
+    EagerPush {
+        message: usize,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+    },
+    IHave {
+        messages: IdSet,
+    },
+    Graft {
+        messages: IdSet,
+    },
+    Prune,
+    Forward {
+        message: usize,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+    },
+    ForwardOk {
+        message: usize,
+    },
+    Digest {
+        digest: Digest,
+    },
+    /// The ids the sender holds, so that the recipient can send those it lacks and ask for
+    /// those it is missing.
+    Reconcile {
+        ids: IdSet,
+    },
+    ReconcileOk {
+        ids: IdSet,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+    },
+    MerkleNodes {
+        nodes: Vec<MerkleNode>,
+    },
+    Ping {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<Update>,
+    },
+    PingOk {
+        incarnation: u32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<Update>,
+    },
+    /// Ask the recipient to ping `target` and pass on its reply.
+    PingReq {
+        target: NodeId,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<Update>,
+    },
+    MerkleBuckets {
+        buckets: Vec<usize>,
+        ids: IdSet,
+        #[serde(default, skip_serializing_if = "Values::is_empty")]
+        values: Values,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<Stamp>,
+    },
+    /// Slots of the log that the sender claimed.
+    Ordered {
+        entries: Vec<LogEntry>,
+    },
+    /// The ids the sender holds that are not yet stable, for working out which ones every node
+    /// holds.
+    Held {
+        ids: IdSet,
+    },
+    /// Ask for the values of ids the sender is missing, which come back in a `ReconcileOk`.
+    Missing {
+        ids: IdSet,
+    },
+}
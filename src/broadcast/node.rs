@@ -0,0 +1,1779 @@
+use std::{
+    collections::{HashMap, HashSet},
+    thread,
+    time::{Duration, Instant},
+};
+
+use gossip_glomers::{
+    context::Context,
+    error::Error,
+    kv::{CasOutcome, KvClient, KvService},
+    membership::{Membership, Update},
+    message::NodeId,
+    node::Node,
+    rpc::RetryPolicy,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::Value;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+use crate::{
+    adaptive::{AdaptiveInterval, Round},
+    causal::{Causal, Stamp, VectorClock},
+    config::{AntiEntropy, BroadcastConfig, Dissemination},
+    id_generator::{GeneratedId, IdGenerator, Snowflake},
+    id_set::IdSet,
+    merkle::MerkleTree,
+    message::{Message, Payload},
+    plumtree::{Outgoing, Plumtree},
+    stability::Stability,
+    topology,
+    total_order::{log_key, Log, LogEntry},
+    value::{id_of, Values},
+    versions::Versions,
+};
+
+/// The longest to wait for `lin-kv` to reply to an attempt at a request.
+const MAX_KV_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Debug)]
+pub struct BroadcastNode {
+    config: BroadcastConfig,
+    round: usize,
+    /// When the next gossip round is due, which probes of the membership don't bring forward.
+    next_round_at: Instant,
+    ids_seen: IdSet,
+    /// The ids seen, in the order they were, so that the number seen is the version. Only kept
+    /// from the first read since a version, when the ids seen before it are taken as seen in
+    /// sorted order.
+    versions: Option<Versions>,
+    /// The values of the ids seen that are not integers.
+    values: Values,
+    /// Set when messages are delivered in causal order.
+    causal: Option<Causal>,
+    /// Set when messages are delivered in total order.
+    log: Option<Log>,
+    /// Whether a read of the first slot of the log that is not known is in flight.
+    syncing_log: bool,
+    lin_kv: KvClient,
+    /// Set when collecting garbage, to tell which of `ids_seen` every node has.
+    stability: Option<Stability>,
+    /// Mirrors `ids_seen` when reconciling with Merkle trees.
+    merkle: Option<MerkleTree>,
+    /// The neighbours from the latest `topology` request.
+    neighbours: HashSet<NodeId>,
+    /// Set when detecting failed peers.
+    membership: Option<Membership>,
+    /// Keyed by the peers this node gossips with: its neighbours, less any that are dead, plus
+    /// the nodes standing in for them.
+    ids_seen_by_neighbours: IdsSeenByNeighbours,
+    plumtree: Plumtree,
+    /// Set when the gossip interval adapts to the neighbours.
+    adaptive: Option<AdaptiveInterval>,
+    /// Scales the adaptive gossip interval, so that nodes don't all gossip at the same instant.
+    jitter: f64,
+    /// When each neighbour that is missing messages started missing them.
+    pending_since: HashMap<NodeId, Instant>,
+    /// When each gossip that may yet be replied to was sent, by msg_id, to time the reply.
+    gossip_sent_at: HashMap<usize, Instant>,
+    /// The msg_id of each message forwarded to each neighbour that it has yet to acknowledge.
+    unacked: HashMap<NodeId, HashMap<usize, usize>>,
+    /// Set when `generate` returns Snowflake ids and this node's number fits in one.
+    snowflake: Option<Snowflake>,
+    /// Picks the partners for `push-pull` gossip.
+    rng: StdRng,
+}
+
+impl Node for BroadcastNode {
+    type Payload = Payload;
+    type Config = BroadcastConfig;
+
+    fn init(config: BroadcastConfig, ctx: &mut Context<Self>) -> Self {
+        let plumtree = Plumtree::new(config.graft_timeout);
+        let membership = config.use_membership.then(|| {
+            let node_id = ctx.node_id().clone();
+            Membership::new(config.membership.clone(), node_id, ctx.node_ids())
+        });
+        let adaptive = config
+            .adaptive
+            .adaptive
+            .then(|| AdaptiveInterval::new(config.adaptive.clone(), config.gossip.interval));
+        let id_number = ctx.node_id().node_number().unwrap_or_default() as u64;
+        let rng = StdRng::seed_from_u64(config.push_pull_seed.wrapping_add(id_number));
+        let snowflake = match config.id_generator {
+            IdGenerator::Uuid => None,
+            IdGenerator::Snowflake => {
+                let node_number = ctx.node_id().node_number().unwrap_or_default();
+                let snowflake = Snowflake::new(node_number, config.max_clock_regression);
+                if snowflake.is_none() {
+                    error!(target: "Node number does not fit in a Snowflake id", node_number);
+                }
+                snowflake
+            }
+        };
+        let kv_policy = RetryPolicy::default()
+            .with_retries(config.kv_retries)
+            .with_backoff(2.0, MAX_KV_TIMEOUT);
+        Self {
+            round: 0,
+            next_round_at: Instant::now() + config.gossip.interval,
+            ids_seen: IdSet::new(),
+            versions: None,
+            values: Values::default(),
+            causal: config.causal.then(Causal::default),
+            log: (config.dissemination == Dissemination::TotalOrder).then(Log::default),
+            syncing_log: false,
+            lin_kv: KvClient::new(KvService::LinKv).with_policy(kv_policy),
+            stability: config.gc.then(Stability::default),
+            merkle: (config.anti_entropy == AntiEntropy::Merkle).then(MerkleTree::default),
+            neighbours: HashSet::new(),
+            membership,
+            ids_seen_by_neighbours: IdsSeenByNeighbours::default(),
+            plumtree,
+            adaptive,
+            jitter: 1.0,
+            pending_since: HashMap::new(),
+            gossip_sent_at: HashMap::new(),
+            unacked: HashMap::new(),
+            snowflake,
+            rng,
+            config,
+        }
+    }
+
+    fn handle(&mut self, request: Message, ctx: &mut Context<Self>) {
+        let src = request.src;
+        let in_reply_to = request.body.msg_id;
+        let reply_to = request.body.in_reply_to;
+        match request.body.payload {
+            Payload::Echo { echo } => handle_echo_request(ctx, echo, src, in_reply_to),
+            Payload::Generate => {
+                let id_generator = self.config.id_generator;
+                let snowflake = self.snowflake.as_mut();
+                handle_generate_request(ctx, id_generator, snowflake, src, in_reply_to);
+            }
+            Payload::Topology { topology } => {
+                let neighbours =
+                    self.config
+                        .topology
+                        .neighbours(ctx.node_id(), ctx.node_ids(), topology);
+                info!(target: "Neighbours", neighbours = ?neighbours);
+                self.neighbours = neighbours;
+                let peers = self.peers(ctx);
+                self.set_peers(ctx, peers);
+                ctx.reply(src, in_reply_to, Payload::TopologyOk);
+            }
+            Payload::Broadcast { message } if self.log.is_some() => {
+                // Acknowledged once the message has a slot in the log.
+                self.append(ctx, message, src, in_reply_to);
+            }
+            Payload::Broadcast { message } => {
+                let message = self.values.insert(message);
+                if self.see(message) {
+                    if let Some(causal) = &mut self.causal {
+                        causal.stamp(ctx.node_id(), message);
+                    }
+                    match self.config.dissemination {
+                        Dissemination::Gossip
+                        | Dissemination::PushPull
+                        | Dissemination::TotalOrder => {}
+                        Dissemination::Plumtree => {
+                            let values = self.values_of(message);
+                            let stamps = self.stamps_of(message);
+                            let outgoing = self.plumtree.deliver(message, &values, &stamps, None);
+                            send_all(ctx, outgoing);
+                        }
+                        Dissemination::Acked => self.forward(ctx, message, None),
+                    }
+                }
+                ctx.reply(src, in_reply_to, Payload::BroadcastOk);
+            }
+            Payload::Read { since } => {
+                ctx.reply(src, in_reply_to, self.read(since));
+            }
+            Payload::Gossip {
+                ids_to_see,
+                values,
+                stamps,
+                clock,
+                updates,
+            } => {
+                self.receive_membership_updates(ctx, updates);
+                self.stagger(ctx.node_id());
+                let mut ids_not_seen_by_other = self.active_ids().difference(&ids_to_see);
+                self.see_all(&ids_to_see, values, stamps);
+                self.observe_clock(&src, clock);
+                self.ids_seen_by_neighbours.update(src.clone(), ids_to_see);
+                if let Some(ids_seen_by_other) = self.ids_seen_by_neighbours.0.get(&src) {
+                    ids_not_seen_by_other = ids_not_seen_by_other.difference(ids_seen_by_other);
+                }
+                if !ids_not_seen_by_other.is_empty() {
+                    let payload = Payload::GossipOk {
+                        values: self.values.for_ids(&ids_not_seen_by_other),
+                        stamps: self.stamps_for_peer(&src),
+                        clock: self.clock(),
+                        ids_to_see: ids_not_seen_by_other,
+                        updates: self.membership_updates_to_send(),
+                    };
+                    ctx.reply(src, in_reply_to, payload);
+                }
+            }
+            Payload::GossipOk {
+                ids_to_see,
+                values,
+                stamps,
+                clock,
+                updates,
+            } => {
+                self.observe_clock(&src, clock);
+                self.receive_membership_updates(ctx, updates);
+                let sent_at = reply_to.and_then(|msg_id| self.gossip_sent_at.remove(&msg_id));
+                if let (Some(adaptive), Some(sent_at)) = (&mut self.adaptive, sent_at) {
+                    adaptive.observe_reply(sent_at.elapsed());
+                }
+                self.see_all(&ids_to_see, values, stamps);
+                self.ids_seen_by_neighbours.update(src, ids_to_see);
+            }
+            Payload::EagerPush {
+                message,
+                values,
+                stamps,
+            } => {
+                let ids_to_see = IdSet::from_iter([message]);
+                self.ids_seen_by_neighbours.update(src.clone(), ids_to_see);
+                self.values.extend(values);
+                self.receive_stamps(stamps);
+                let outgoing = match self.see(message) {
+                    true => {
+                        let values = self.values_of(message);
+                        let stamps = self.stamps_of(message);
+                        self.plumtree.deliver(message, &values, &stamps, Some(&src))
+                    }
+                    false => self.plumtree.duplicate(message, &src),
+                };
+                send_all(ctx, outgoing);
+            }
+            Payload::IHave { messages } => {
+                let ids_not_seen_by_self = messages.difference(&self.ids_seen);
+                self.ids_seen_by_neighbours.update(src.clone(), messages);
+                self.plumtree
+                    .i_have(&src, ids_not_seen_by_self.iter(), Instant::now());
+            }
+            Payload::Graft { messages } => {
+                self.plumtree.graft(&src);
+                for message in messages.intersection(&self.ids_seen).iter() {
+                    let payload = Payload::EagerPush {
+                        message,
+                        values: self.values_of(message),
+                        stamps: self.stamps_of(message),
+                    };
+                    ctx.send(src.clone(), payload);
+                }
+            }
+            Payload::Prune => self.plumtree.prune(&src),
+            Payload::Digest { digest } => {
+                if digest != self.ids_seen.digest() {
+                    let ids = self.ids_seen.clone();
+                    ctx.send(src, Payload::Reconcile { ids });
+                }
+            }
+            Payload::Reconcile { ids } => {
+                let ids_not_seen_by_other = self.ids_seen.difference(&ids);
+                let ids_not_seen_by_self = ids.difference(&self.ids_seen);
+                let ids = match &self.stability {
+                    Some(stability) => ids.difference(stability.stable()),
+                    None => ids,
+                };
+                self.ids_seen_by_neighbours.replace(src.clone(), ids);
+                if !ids_not_seen_by_other.is_empty() {
+                    let payload = Payload::ReconcileOk {
+                        values: self.values.for_ids(&ids_not_seen_by_other),
+                        stamps: self.stamps_for(&ids_not_seen_by_other),
+                        ids: ids_not_seen_by_other,
+                    };
+                    ctx.send(src.clone(), payload);
+                }
+                if !ids_not_seen_by_self.is_empty() {
+                    let ids = ids_not_seen_by_self;
+                    ctx.send(src, Payload::Missing { ids });
+                }
+            }
+            Payload::Missing { ids } => {
+                let ids = ids.intersection(&self.ids_seen);
+                if !ids.is_empty() {
+                    let payload = Payload::ReconcileOk {
+                        values: self.values.for_ids(&ids),
+                        stamps: self.stamps_for(&ids),
+                        ids,
+                    };
+                    ctx.send(src, payload);
+                }
+            }
+            Payload::ReconcileOk {
+                ids,
+                values,
+                stamps,
+            } => {
+                self.see_all(&ids, values, stamps);
+                self.ids_seen_by_neighbours.update(src, ids);
+            }
+            Payload::MerkleNodes { nodes } => {
+                let Some(merkle) = &self.merkle else {
+                    error!(target: "Merkle anti-entropy is off", src = %src);
+                    return;
+                };
+                let comparison = merkle.compare(&nodes);
+                if !comparison.children.is_empty() {
+                    let payload = Payload::MerkleNodes {
+                        nodes: comparison.children,
+                    };
+                    ctx.send(src.clone(), payload);
+                }
+                if !comparison.buckets.is_empty() {
+                    let ids = merkle.bucket_ids(&comparison.buckets);
+                    let buckets = comparison.buckets;
+                    let values = self.values.for_ids(&ids);
+                    let stamps = self.stamps_for(&ids);
+                    let payload = Payload::MerkleBuckets {
+                        buckets,
+                        ids,
+                        values,
+                        stamps,
+                    };
+                    ctx.send(src, payload);
+                }
+            }
+            Payload::MerkleBuckets {
+                buckets,
+                ids,
+                values,
+                stamps,
+            } => {
+                let Some(merkle) = &self.merkle else {
+                    error!(target: "Merkle anti-entropy is off", src = %src);
+                    return;
+                };
+                let ids_not_seen_by_other = merkle.bucket_ids(&buckets).difference(&ids);
+                self.see_all(&ids, values, stamps);
+                self.ids_seen_by_neighbours.update(src.clone(), ids);
+                if !ids_not_seen_by_other.is_empty() {
+                    let payload = Payload::ReconcileOk {
+                        values: self.values.for_ids(&ids_not_seen_by_other),
+                        stamps: self.stamps_for(&ids_not_seen_by_other),
+                        ids: ids_not_seen_by_other,
+                    };
+                    ctx.send(src, payload);
+                }
+            }
+            Payload::Forward {
+                message,
+                values,
+                stamps,
+            } => {
+                ctx.reply(src.clone(), in_reply_to, Payload::ForwardOk { message });
+                let ids_to_see = IdSet::from_iter([message]);
+                self.ids_seen_by_neighbours.update(src.clone(), ids_to_see);
+                self.values.extend(values);
+                self.receive_stamps(stamps);
+                if self.see(message) {
+                    self.forward(ctx, message, Some(&src));
+                }
+            }
+            Payload::Ping { updates } => {
+                let updates = self.apply_membership_updates(ctx, updates);
+                let incarnation = self.membership.as_ref().map_or(0, Membership::incarnation);
+                let payload = Payload::PingOk {
+                    incarnation,
+                    updates,
+                };
+                ctx.reply(src, in_reply_to, payload);
+            }
+            Payload::PingReq { target, updates } => {
+                let updates = self.apply_membership_updates(ctx, updates);
+                let policy = RetryPolicy::new(self.config.membership.probe_timeout);
+                let payload = Payload::Ping { updates };
+                ctx.rpc(
+                    target,
+                    payload,
+                    policy,
+                    move |node: &mut Self, reply, ctx| {
+                        if let Ok(Payload::PingOk {
+                            incarnation,
+                            updates,
+                        }) = reply
+                        {
+                            let updates = node.apply_membership_updates(ctx, updates);
+                            let payload = Payload::PingOk {
+                                incarnation,
+                                updates,
+                            };
+                            ctx.reply(src, in_reply_to, payload);
+                        }
+                    },
+                );
+            }
+            Payload::Held { ids } => {
+                let Some(stability) = &mut self.stability else {
+                    error!(target: "Garbage collection is off", src = %src);
+                    return;
+                };
+                let others = other_node_ids(ctx);
+                let newly_stable = stability.report(src, &ids, &others);
+                if !newly_stable.is_empty() {
+                    debug!(target: "Stable", ids = ?newly_stable);
+                    self.ids_seen_by_neighbours.forget(stability.stable());
+                }
+            }
+            Payload::Ordered { entries } => {
+                for entry in entries {
+                    self.order(entry.index, entry.message);
+                }
+            }
+            // Late replies to requests that were already answered or given up on.
+            Payload::ForwardOk { .. } | Payload::PingOk { .. } => {}
+            payload => {
+                error!(target: "invalid payload", payload = ?payload);
+                let text = format!("unexpected payload {payload:?}");
+                ctx.reply_error(src, in_reply_to, Error::NotSupported(text));
+            }
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context<Self>) {
+        if let Some(membership) = &mut self.membership {
+            let now = Instant::now();
+            membership.expire_suspects(now);
+            if let Some(target) = membership.next_probe(now) {
+                self.probe(ctx, target);
+            }
+            self.refresh_peers(ctx);
+        }
+        let now = Instant::now();
+        if self.round_interval().is_none() || now < self.next_round_at {
+            return;
+        }
+        self.gossip_round(ctx);
+        if let Some(interval) = self.round_interval() {
+            self.next_round_at = now + interval;
+        }
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let until_round = self
+            .round_interval()
+            .map(|_| self.next_round_at.saturating_duration_since(now));
+        let probe_interval = self
+            .membership
+            .as_ref()
+            .map(|_| self.config.membership.probe_interval);
+        until_round.into_iter().chain(probe_interval).min()
+    }
+}
+
+impl BroadcastNode {
+    /// Disseminate, start anti-entropy and report the ids held, as each is due this round.
+    fn gossip_round(&mut self, ctx: &mut Context<Self>) {
+        match self.config.dissemination {
+            Dissemination::Gossip => self.gossip(ctx),
+            Dissemination::Acked => {}
+            Dissemination::PushPull => self.push_pull(ctx),
+            Dissemination::TotalOrder => self.sync_log(ctx),
+            Dissemination::Plumtree => {
+                let ids_seen_by_neighbours = &self.ids_seen_by_neighbours;
+                let ids_seen = &self.ids_seen;
+                let outgoing = self.plumtree.tick(
+                    Instant::now(),
+                    |neighbour, id| ids_seen_by_neighbours.has_seen(neighbour, id),
+                    |id| ids_seen.contains(id),
+                );
+                send_all(ctx, outgoing);
+            }
+        }
+        if self.round % self.config.anti_entropy_rounds == 0 {
+            match self.config.anti_entropy {
+                AntiEntropy::None => {}
+                AntiEntropy::Digest => {
+                    let digest = self.ids_seen.digest();
+                    self.start_anti_entropy(ctx, Payload::Digest { digest });
+                }
+                AntiEntropy::Merkle => {
+                    if let Some(merkle) = &self.merkle {
+                        let nodes = vec![merkle.root()];
+                        self.start_anti_entropy(ctx, Payload::MerkleNodes { nodes });
+                    }
+                }
+            }
+        }
+        if let Some(stability) = &mut self.stability {
+            if self.round % self.config.gc_rounds == 0 {
+                let ids = stability.take_report();
+                for other in other_node_ids(ctx) {
+                    ctx.send(other, Payload::Held { ids: ids.clone() });
+                }
+            }
+        }
+        self.round += 1;
+    }
+
+    /// How long from one gossip round to the next, if there are any.
+    fn round_interval(&self) -> Option<Duration> {
+        match (self.config.dissemination, self.config.anti_entropy) {
+            // Still ticks to report the ids held when collecting garbage.
+            (Dissemination::Acked, AntiEntropy::None) if self.stability.is_none() => None,
+            _ => match &self.adaptive {
+                Some(adaptive) => Some(adaptive.interval().mul_f64(self.jitter)),
+                None => Some(self.config.gossip.interval),
+            },
+        }
+    }
+    /// The messages seen, in the order they were delivered if that is constrained, and only
+    /// those delivered after version `since` of this node if there is one.
+    ///
+    /// A version counts the messages delivered, in the order that `read` would return them
+    /// when that is constrained and the order they were seen otherwise.
+    fn read(&mut self, since: Option<usize>) -> Payload {
+        let start = since.unwrap_or_default();
+        if let Some(log) = &self.log {
+            let delivered = log.delivered();
+            let log: Vec<LogEntry> = delivered
+                .get(start..)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|(index, id)| {
+                    let message = self.values.get(*id)?;
+                    Some(LogEntry {
+                        index: *index,
+                        message,
+                    })
+                })
+                .collect();
+            let messages = log.iter().map(|entry| entry.message.clone()).collect();
+            let version = since.map(|_| delivered.len());
+            return Payload::ReadOk {
+                messages,
+                version,
+                log,
+            };
+        }
+        if let (None, Some(since)) = (&self.causal, since) {
+            let versions = self
+                .versions
+                .get_or_insert_with(|| Versions::from_ids(&self.ids_seen));
+            let messages = versions
+                .since(since)
+                .filter_map(|id| self.values.get(id))
+                .collect();
+            return Payload::ReadOk {
+                messages,
+                version: Some(versions.version()),
+                log: Vec::new(),
+            };
+        }
+        let ordered = self.causal.as_ref().map(Causal::delivered);
+        let messages = match ordered {
+            Some(ordered) => ordered
+                .get(start..)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|id| self.values.get(*id))
+                .collect(),
+            None => self
+                .ids_seen
+                .iter()
+                .filter_map(|id| self.values.get(id))
+                .collect(),
+        };
+        let version = ordered.filter(|_| since.is_some()).map(<[usize]>::len);
+        Payload::ReadOk {
+            messages,
+            version,
+            log: Vec::new(),
+        }
+    }
+
+    /// The peers to gossip with: the neighbours that are not dead, and for each one that is,
+    /// the next live node after it that is not already a peer.
+    fn peers(&self, ctx: &Context<Self>) -> HashSet<NodeId> {
+        let Some(membership) = &self.membership else {
+            return self.neighbours.clone();
+        };
+        let mut node_ids: Vec<_> = ctx.node_ids().iter().collect();
+        node_ids.sort();
+        let mut peers: HashSet<NodeId> = self
+            .neighbours
+            .iter()
+            .filter(|neighbour| !membership.is_dead(neighbour))
+            .cloned()
+            .collect();
+        let mut dead_neighbours: Vec<_> = self
+            .neighbours
+            .iter()
+            .filter(|neighbour| membership.is_dead(neighbour))
+            .collect();
+        dead_neighbours.sort();
+        for neighbour in dead_neighbours {
+            let position = node_ids
+                .iter()
+                .position(|node_id| *node_id == neighbour)
+                .unwrap_or_default();
+            let substitute = node_ids
+                .iter()
+                .cycle()
+                .skip(position + 1)
+                .take(node_ids.len())
+                .find(|node_id| {
+                    **node_id != ctx.node_id()
+                        && !membership.is_dead(node_id)
+                        && !self.neighbours.contains(node_id)
+                        && !peers.contains(node_id)
+                });
+            if let Some(substitute) = substitute {
+                peers.insert((*substitute).clone());
+            }
+        }
+        peers
+    }
+
+    fn set_peers(&mut self, ctx: &mut Context<Self>, peers: HashSet<NodeId>) {
+        self.plumtree.set_neighbours(peers.iter().cloned());
+        self.unacked.retain(|peer, forwards| {
+            let is_peer = peers.contains(peer);
+            if !is_peer {
+                forwards.values().for_each(|msg_id| {
+                    ctx.cancel_rpc(*msg_id);
+                });
+            }
+            is_peer
+        });
+        self.ids_seen_by_neighbours.set_neighbours(peers);
+        if self.config.dissemination == Dissemination::Acked {
+            let ids_seen: Vec<usize> = self.ids_seen.iter().collect();
+            for id in ids_seen {
+                self.forward(ctx, id, None);
+            }
+        }
+    }
+
+    /// Swap peers in and out as the membership view changes.
+    fn refresh_peers(&mut self, ctx: &mut Context<Self>) {
+        let peers = self.peers(ctx);
+        let current: HashSet<NodeId> = self.ids_seen_by_neighbours.0.keys().cloned().collect();
+        if peers != current {
+            if let Some(membership) = &self.membership {
+                let view: Vec<_> = membership.view().collect();
+                info!(target: "Membership changed", ?view, ?peers);
+            }
+            self.set_peers(ctx, peers);
+        }
+    }
+
+    /// Apply membership updates from a peer, returning those to piggyback on the reply.
+    fn apply_membership_updates(
+        &mut self,
+        ctx: &mut Context<Self>,
+        updates: Vec<Update>,
+    ) -> Vec<Update> {
+        self.receive_membership_updates(ctx, updates);
+        self.membership_updates_to_send()
+    }
+
+    fn receive_membership_updates(&mut self, ctx: &mut Context<Self>, updates: Vec<Update>) {
+        let Some(membership) = &mut self.membership else {
+            return;
+        };
+        if updates.is_empty() {
+            return;
+        }
+        membership.apply(updates, Instant::now());
+        self.refresh_peers(ctx);
+    }
+
+    /// Membership updates to piggyback on a message, if this node tracks membership.
+    fn membership_updates_to_send(&mut self) -> Vec<Update> {
+        self.membership
+            .as_mut()
+            .map(Membership::updates_to_send)
+            .unwrap_or_default()
+    }
+
+    fn probe(&mut self, ctx: &mut Context<Self>, target: NodeId) {
+        let updates = self.membership_updates_to_send();
+        let policy = RetryPolicy::new(self.config.membership.probe_timeout);
+        let payload = Payload::Ping { updates };
+        ctx.rpc(
+            target.clone(),
+            payload,
+            policy,
+            move |node: &mut Self, reply, ctx| node.handle_probe_reply(ctx, target, reply, true),
+        );
+    }
+
+    fn handle_probe_reply(
+        &mut self,
+        ctx: &mut Context<Self>,
+        target: NodeId,
+        reply: Result<Payload, Error>,
+        is_direct: bool,
+    ) {
+        let Some(membership) = &mut self.membership else {
+            return;
+        };
+        let now = Instant::now();
+        match reply {
+            Ok(Payload::PingOk {
+                incarnation,
+                updates,
+            }) => {
+                membership.apply(updates, now);
+                membership.probe_succeeded(&target, incarnation, now);
+            }
+            Ok(payload) => error!(target: "invalid reply", payload = ?payload),
+            Err(_) if is_direct => {
+                let helpers = membership.direct_probe_failed(&target, now);
+                // Helpers ping the target and then reply, so allow for two round trips.
+                let policy = RetryPolicy::new(2 * self.config.membership.probe_timeout);
+                for helper in helpers {
+                    let updates = membership.updates_to_send();
+                    let target = target.clone();
+                    let payload = Payload::PingReq {
+                        target: target.clone(),
+                        updates,
+                    };
+                    ctx.rpc(
+                        helper,
+                        payload,
+                        policy,
+                        move |node: &mut Self, reply, ctx| {
+                            node.handle_probe_reply(ctx, target, reply, false)
+                        },
+                    );
+                }
+            }
+            Err(_) => membership.indirect_probe_failed(&target, now),
+        }
+        self.refresh_peers(ctx);
+    }
+
+    /// Forward `message` to every neighbour but `from` that is not known to have it, retrying
+    /// with backoff until each one acknowledges it.
+    fn forward(&mut self, ctx: &mut Context<Self>, message: usize, from: Option<&NodeId>) {
+        let policy = RetryPolicy::new(self.config.forward_timeout)
+            .with_retries(None)
+            .with_backoff(2.0, self.config.max_forward_timeout);
+        let neighbours: Vec<NodeId> = self
+            .ids_seen_by_neighbours
+            .0
+            .keys()
+            .filter(|neighbour| Some(*neighbour) != from)
+            .filter(|neighbour| !self.ids_seen_by_neighbours.has_seen(neighbour, message))
+            .filter(|neighbour| {
+                self.unacked
+                    .get(*neighbour)
+                    .is_none_or(|forwards| !forwards.contains_key(&message))
+            })
+            .cloned()
+            .collect();
+        for neighbour in neighbours {
+            let payload = Payload::Forward {
+                message,
+                values: self.values_of(message),
+                stamps: self.stamps_of(message),
+            };
+            let dest = neighbour.clone();
+            let msg_id = ctx.rpc(
+                neighbour.clone(),
+                payload,
+                policy,
+                move |node: &mut Self, reply, _| node.handle_forward_reply(dest, message, reply),
+            );
+            let forwards = self.unacked.entry(neighbour).or_default();
+            forwards.insert(message, msg_id);
+        }
+    }
+
+    fn handle_forward_reply(
+        &mut self,
+        neighbour: NodeId,
+        message: usize,
+        reply: Result<Payload, Error>,
+    ) {
+        if let Some(forwards) = self.unacked.get_mut(&neighbour) {
+            forwards.remove(&message);
+        }
+        match reply {
+            Ok(Payload::ForwardOk { message }) => {
+                let ids_seen = IdSet::from_iter([message]);
+                self.ids_seen_by_neighbours.update(neighbour, ids_seen);
+            }
+            Ok(payload) => error!(target: "invalid reply", payload = ?payload),
+            Err(error) => info!(target: "Forward failed", error = %error),
+        }
+    }
+
+    /// Send each neighbour due a round of gossip the messages it is not known to have.
+    fn gossip(&mut self, ctx: &mut Context<Self>) {
+        if self.active_ids().is_empty() {
+            return;
+        }
+        self.stagger(ctx.node_id());
+        if self.adaptive.is_some() {
+            self.oldest_pending();
+        }
+        let now = Instant::now();
+        let neighbours = self.ids_seen_by_neighbours.neighbours();
+        let to_gossip: Vec<_> = self
+            .config
+            .gossip
+            .peers_for_round(self.round, &neighbours)
+            .filter_map(|neighbour| {
+                let ids_seen_by_neighbour = &self.ids_seen_by_neighbours.0[*neighbour];
+                let ids_to_see = self.active_ids().difference(ids_seen_by_neighbour);
+                if ids_to_see.is_empty() {
+                    return None;
+                }
+                if let Some(adaptive) = &self.adaptive {
+                    let wait = self
+                        .pending_since
+                        .get(*neighbour)
+                        .map_or(Duration::ZERO, |since| now.duration_since(*since));
+                    if ids_to_see.len() < adaptive.batch() && !adaptive.is_urgent(wait) {
+                        return None;
+                    }
+                }
+                Some(((*neighbour).clone(), ids_to_see))
+            })
+            .collect();
+        let mut round = Round::default();
+        let mut sent = Vec::new();
+        for (neighbour, ids_to_see) in to_gossip {
+            round.messages += 1;
+            round.ids += ids_to_see.len();
+            let payload = Payload::Gossip {
+                values: self.values.for_ids(&ids_to_see),
+                stamps: self.stamps_for_peer(&neighbour),
+                clock: self.clock(),
+                ids_to_see,
+                updates: self.membership_updates_to_send(),
+            };
+            sent.push(ctx.send(neighbour, payload));
+        }
+        if self.adaptive.is_some() {
+            self.gossip_sent_at
+                .extend(sent.into_iter().map(|msg_id| (msg_id, now)));
+            round.oldest_pending = self.oldest_pending();
+            if let Some(adaptive) = &mut self.adaptive {
+                adaptive.observe(round);
+                debug!(
+                    target: "Gossip round",
+                    ?round,
+                    interval = ?adaptive.interval(),
+                    batch = adaptive.batch(),
+                    reply_latency = ?adaptive.reply_latency()
+                );
+                // Gossip that is not replied to by then never will be.
+                let forget_before = adaptive.latency_target() * 2;
+                self.gossip_sent_at
+                    .retain(|_, sent_at| now.duration_since(*sent_at) < forget_before);
+            }
+            self.jitter = rand::thread_rng().gen_range(0.8..1.2);
+        }
+    }
+
+    /// Claim the first slot of the log that is not known for `message`, moving on to the next
+    /// whenever another node got there first, and acknowledge the broadcast once it has a slot.
+    fn append(
+        &mut self,
+        ctx: &mut Context<Self>,
+        message: Value,
+        client: NodeId,
+        in_reply_to: Option<usize>,
+    ) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        if log.contains(id_of(&message)) {
+            ctx.reply(client, in_reply_to, Payload::BroadcastOk);
+            return;
+        }
+        let index = log.next_index();
+        let key = log_key(index);
+        // Claiming the slot with its own message again succeeds, so retries are harmless.
+        let from = message.clone();
+        let to = message.clone();
+        self.lin_kv.cas(
+            ctx,
+            key,
+            from,
+            to,
+            true,
+            move |node: &mut Self, outcome, ctx| match outcome {
+                Ok(CasOutcome::Swapped) => {
+                    node.order(index, message.clone());
+                    let entry = LogEntry {
+                        index,
+                        message: message.clone(),
+                    };
+                    let others = other_node_ids(ctx);
+                    for other in others {
+                        let entries = vec![entry.clone()];
+                        ctx.send(other, Payload::Ordered { entries });
+                    }
+                    node.append(ctx, message, client, in_reply_to);
+                }
+                Ok(CasOutcome::PreconditionFailed | CasOutcome::KeyDoesNotExist) => {
+                    let key = log_key(index);
+                    node.lin_kv.read(
+                        ctx,
+                        key,
+                        move |node: &mut Self, slot: Result<Option<Value>, Error>, ctx| match slot {
+                            Ok(slot) => {
+                                if let Some(winner) = slot {
+                                    node.order(index, winner);
+                                }
+                                node.append(ctx, message, client, in_reply_to);
+                            }
+                            Err(error) => node.fail_append(ctx, error, client, in_reply_to),
+                        },
+                    );
+                }
+                Err(error) => node.fail_append(ctx, error, client, in_reply_to),
+            },
+        );
+    }
+
+    /// Give up on a broadcast whose `lin-kv` request failed even after retrying with backoff,
+    /// and let the client decide whether to broadcast it again.
+    fn fail_append(
+        &mut self,
+        ctx: &mut Context<Self>,
+        error: Error,
+        client: NodeId,
+        in_reply_to: Option<usize>,
+    ) {
+        info!(target: "Append failed", error = %error);
+        ctx.reply_error(client, in_reply_to, error);
+    }
+
+    /// Read the first slot of the log that is not known, and those after it until one is empty.
+    fn sync_log(&mut self, ctx: &mut Context<Self>) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        if self.syncing_log {
+            return;
+        }
+        self.syncing_log = true;
+        let index = log.next_index();
+        self.lin_kv.read(
+            ctx,
+            log_key(index),
+            move |node: &mut Self, slot: Result<Option<Value>, Error>, ctx| {
+                node.syncing_log = false;
+                if let Ok(Some(message)) = slot {
+                    node.order(index, message);
+                    node.sync_log(ctx);
+                }
+            },
+        );
+    }
+
+    /// Record that slot `index` of the log holds `message`.
+    fn order(&mut self, index: usize, message: Value) {
+        let id = self.values.insert(message);
+        let Some(log) = &mut self.log else {
+            return;
+        };
+        for id in log.insert(index, id) {
+            self.see(id);
+        }
+    }
+
+    /// Send a few random live nodes a digest of the ids this node has seen, so that any that
+    /// differ reconcile with it and each side is sent only the values it is missing.
+    fn push_pull(&mut self, ctx: &mut Context<Self>) {
+        let mut node_ids: Vec<NodeId> = ctx
+            .node_ids()
+            .iter()
+            .filter(|node_id| {
+                self.membership
+                    .as_ref()
+                    .is_none_or(|membership| !membership.is_dead(node_id))
+            })
+            .cloned()
+            .collect();
+        node_ids.sort();
+        let fanout = self.config.push_pull_fanout;
+        let peers = topology::random_peers(&node_ids, ctx.node_id(), fanout, &mut self.rng);
+        let digest = self.ids_seen.digest();
+        for peer in peers {
+            ctx.send(peer.clone(), Payload::Digest { digest });
+        }
+    }
+
+    /// How long the neighbour that has been missing messages the longest has been missing them.
+    fn oldest_pending(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let neighbours = &self.ids_seen_by_neighbours.0;
+        self.pending_since
+            .retain(|neighbour, _| neighbours.contains_key(neighbour));
+        for (neighbour, ids_seen_by_neighbour) in neighbours {
+            let is_pending = !self
+                .active_ids()
+                .difference(ids_seen_by_neighbour)
+                .is_empty();
+            match is_pending {
+                true => {
+                    self.pending_since.entry(neighbour.clone()).or_insert(now);
+                }
+                false => {
+                    self.pending_since.remove(neighbour);
+                }
+            }
+        }
+        self.pending_since
+            .values()
+            .min()
+            .map(|since| now.duration_since(*since))
+    }
+
+    /// Ask this round's neighbours to compare the messages they have seen with this node's,
+    /// as summarised by `payload`.
+    fn start_anti_entropy(&self, ctx: &mut Context<Self>, payload: Payload) {
+        let neighbours = self.ids_seen_by_neighbours.neighbours();
+        let round = self.round / self.config.anti_entropy_rounds;
+        self.config
+            .gossip
+            .peers_for_round(round, &neighbours)
+            .for_each(|neighbour| {
+                ctx.send((*neighbour).clone(), payload.clone());
+            });
+    }
+
+    /// The values to send along with `id`.
+    fn values_of(&self, id: usize) -> Values {
+        self.values.for_ids(&IdSet::from_iter([id]))
+    }
+
+    /// The ids seen that another node may still be missing.
+    fn active_ids(&self) -> &IdSet {
+        self.stability
+            .as_ref()
+            .map_or(&self.ids_seen, Stability::unstable)
+    }
+
+    /// The causal stamps to send along with `ids`.
+    fn stamps_for(&self, ids: &IdSet) -> Vec<Stamp> {
+        self.causal
+            .as_ref()
+            .map(|causal| causal.stamps_for(ids))
+            .unwrap_or_default()
+    }
+
+    /// The causal stamps that `peer` may be missing, to send along with gossip.
+    fn stamps_for_peer(&self, peer: &NodeId) -> Vec<Stamp> {
+        self.causal
+            .as_ref()
+            .map(|causal| causal.stamps_for_peer(peer))
+            .unwrap_or_default()
+    }
+
+    fn clock(&self) -> VectorClock {
+        self.causal
+            .as_ref()
+            .map(|causal| causal.clock().clone())
+            .unwrap_or_default()
+    }
+
+    fn observe_clock(&mut self, peer: &NodeId, clock: VectorClock) {
+        if let Some(causal) = &mut self.causal {
+            causal.observe_clock(peer.clone(), clock);
+        }
+    }
+
+    fn stamps_of(&self, id: usize) -> Vec<Stamp> {
+        self.stamps_for(&IdSet::from_iter([id]))
+    }
+
+    fn receive_stamps(&mut self, stamps: Vec<Stamp>) {
+        if let Some(causal) = &mut self.causal {
+            causal.receive(stamps);
+        }
+    }
+
+    /// Record `id` as seen, returning whether it is new.
+    fn see(&mut self, id: usize) -> bool {
+        let is_new = self.ids_seen.insert(id);
+        if let (true, Some(versions)) = (is_new, &mut self.versions) {
+            versions.push(id);
+        }
+        if let (true, Some(merkle)) = (is_new, &mut self.merkle) {
+            merkle.insert(id);
+        }
+        if let (true, Some(stability)) = (is_new, &mut self.stability) {
+            stability.see(&IdSet::from_iter([id]));
+        }
+        is_new
+    }
+
+    /// Record `ids` as seen, storing the `values` and `stamps` that came with them.
+    fn see_all(&mut self, ids: &IdSet, values: Values, stamps: Vec<Stamp>) {
+        self.values.extend(values);
+        self.receive_stamps(stamps);
+        let new_ids = ids.difference(&self.ids_seen);
+        if let Some(versions) = &mut self.versions {
+            versions.extend(&new_ids);
+        }
+        if let Some(merkle) = &mut self.merkle {
+            new_ids.iter().for_each(|id| merkle.insert(id));
+        }
+        if let Some(stability) = &mut self.stability {
+            stability.see(&new_ids);
+        }
+        self.ids_seen.union_with(ids);
+    }
+
+    fn stagger(&self, node_id: &NodeId) {
+        if self.adaptive.is_some() {
+            return;
+        }
+        let id_number = node_id.node_number().unwrap_or_default();
+        thread::sleep(self.config.gossip.stagger(id_number));
+    }
+}
+
+/// Every node but this one.
+fn other_node_ids(ctx: &Context<BroadcastNode>) -> Vec<NodeId> {
+    ctx.node_ids()
+        .iter()
+        .filter(|node_id| *node_id != ctx.node_id())
+        .cloned()
+        .collect()
+}
+
+fn send_all(ctx: &mut Context<BroadcastNode>, outgoing: Outgoing) {
+    for (dest, payload) in outgoing {
+        ctx.send(dest, payload);
+    }
+}
+
+fn handle_echo_request(
+    ctx: &mut Context<BroadcastNode>,
+    echo: String,
+    dest: NodeId,
+    in_reply_to: impl Into<Option<usize>>,
+) {
+    let response_payload = Payload::EchoOk { echo };
+    ctx.reply(dest, in_reply_to, response_payload);
+}
+
+fn handle_generate_request(
+    ctx: &mut Context<BroadcastNode>,
+    id_generator: IdGenerator,
+    snowflake: Option<&mut Snowflake>,
+    dest: NodeId,
+    in_reply_to: impl Into<Option<usize>>,
+) {
+    let id = match (id_generator, snowflake) {
+        (IdGenerator::Uuid, _) => GeneratedId::Uuid(Uuid::new_v4()),
+        (IdGenerator::Snowflake, Some(snowflake)) => match snowflake.next_id() {
+            Ok(id) => GeneratedId::Snowflake(id),
+            Err(unavailable) => {
+                let text = format!("no Snowflake id available: {unavailable:?}");
+                ctx.reply_error(dest, in_reply_to, Error::TemporarilyUnavailable(text));
+                return;
+            }
+        },
+        (IdGenerator::Snowflake, None) => {
+            let text = "this node's number does not fit in a Snowflake id".to_string();
+            ctx.reply_error(dest, in_reply_to, Error::NotSupported(text));
+            return;
+        }
+    };
+    let response_payload = Payload::GenerateOk { id };
+    ctx.reply(dest, in_reply_to, response_payload);
+}
+
+#[derive(Debug, Default)]
+pub struct IdsSeenByNeighbours(HashMap<NodeId, IdSet>);
+
+impl IdsSeenByNeighbours {
+    /// Replace the neighbours, remembering what those that remain have already seen.
+    fn set_neighbours(&mut self, neighbours: HashSet<NodeId>) {
+        self.0.retain(|neighbour, _| neighbours.contains(neighbour));
+        for neighbour in neighbours {
+            self.0.entry(neighbour).or_default();
+        }
+    }
+    /// The neighbours in a stable order.
+    fn neighbours(&self) -> Vec<&NodeId> {
+        let mut neighbours: Vec<_> = self.0.keys().collect();
+        neighbours.sort();
+        neighbours
+    }
+    fn has_seen(&self, neighbour: &NodeId, id: usize) -> bool {
+        self.0
+            .get(neighbour)
+            .is_some_and(|ids_seen| ids_seen.contains(id))
+    }
+    /// Record exactly what `neighbour` has seen, as learnt from reconciling with it.
+    fn replace(&mut self, neighbour: NodeId, ids_seen: IdSet) {
+        if let Some(ids_seen_by_neighbour) = self.0.get_mut(&neighbour) {
+            *ids_seen_by_neighbour = ids_seen;
+        }
+    }
+    fn update(&mut self, neighbour: NodeId, ids_seen: IdSet) {
+        if let Some(ids_seen_by_neighbour) = self.0.get_mut(&neighbour) {
+            ids_seen_by_neighbour.union_with(&ids_seen);
+        }
+    }
+    /// Stop tracking `ids`, which every node has.
+    fn forget(&mut self, ids: &IdSet) {
+        for ids_seen_by_neighbour in self.0.values_mut() {
+            *ids_seen_by_neighbour = ids_seen_by_neighbour.difference(ids);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use clap::Parser;
+    use gossip_glomers::{config::Cli, membership::Liveness, runtime};
+    use serde::Serialize;
+    use serde_json::json;
+
+    use super::*;
+
+    /// A message as the node sent it.
+    type Sent = gossip_glomers::message::Message<Value>;
+
+    /// Node `n1` of `n0` to `n2`, started with `args`, and a context that keeps what it sends.
+    fn node(args: &[&str]) -> (BroadcastNode, Context<BroadcastNode>) {
+        cluster_node(1, 0..3, args)
+    }
+
+    /// Node `number` of the nodes numbered `numbers`, started with `args`.
+    fn cluster_node(
+        number: usize,
+        numbers: Range<usize>,
+        args: &[&str],
+    ) -> (BroadcastNode, Context<BroadcastNode>) {
+        let cli = Cli::<BroadcastConfig>::parse_from(["node"].iter().chain(args));
+        let node_ids = numbers.map(NodeId::new).collect();
+        let mut ctx = Context::new(NodeId::new(number), node_ids, 0).with_outbox();
+        (BroadcastNode::init(cli.node, &mut ctx), ctx)
+    }
+
+    /// Hand everything `from` has sent to `node`.
+    fn deliver(
+        from: &mut Context<BroadcastNode>,
+        node: &mut BroadcastNode,
+        ctx: &mut Context<BroadcastNode>,
+    ) {
+        for message in from.take_sent() {
+            let payload = serde_json::from_value(message.body.payload)
+                .expect("Nodes send their own payloads");
+            let msg_id = message.body.msg_id;
+            let message = Message::new(message.src, message.dest, msg_id, None, payload);
+            node.handle(message, ctx);
+        }
+    }
+
+    fn handle(
+        node: &mut BroadcastNode,
+        ctx: &mut Context<BroadcastNode>,
+        src: NodeId,
+        payload: Payload,
+    ) {
+        let request = Message::new(src, NodeId::new(1), Some(1), None, payload);
+        node.handle(request, ctx);
+    }
+
+    fn take_sent(ctx: &mut Context<BroadcastNode>) -> Vec<(NodeId, Payload)> {
+        ctx.take_sent()
+            .into_iter()
+            .map(|message| {
+                let payload = serde_json::from_value(message.body.payload)
+                    .expect("Nodes send their own payloads");
+                (message.dest, payload)
+            })
+            .collect()
+    }
+
+    fn read(
+        node: &mut BroadcastNode,
+        ctx: &mut Context<BroadcastNode>,
+        since: Option<usize>,
+    ) -> (Vec<Value>, Option<usize>) {
+        handle(node, ctx, NodeId::Client(1), Payload::Read { since });
+        let sent = take_sent(ctx);
+        let [(
+            _,
+            Payload::ReadOk {
+                messages, version, ..
+            },
+        )] = &sent[..]
+        else {
+            panic!("expected a read reply, got {sent:?}");
+        };
+        (messages.clone(), *version)
+    }
+
+    /// Answer `request`, sent by `node`, with `payload`.
+    fn answer(
+        node: &mut BroadcastNode,
+        ctx: &mut Context<BroadcastNode>,
+        request: &Sent,
+        payload: impl Serialize,
+    ) {
+        let payload = serde_json::to_value(payload).expect("Replies are serializable");
+        let (src, dest) = (request.dest.clone(), request.src.clone());
+        let reply = Sent::new(src, dest, None, request.body.msg_id, payload);
+        runtime::dispatch(node, ctx, &reply.to_string());
+    }
+
+    /// Run the callbacks of requests that time out `after` now, as the runtime would.
+    fn expire(node: &mut BroadcastNode, ctx: &mut Context<BroadcastNode>, after: Duration) {
+        for (callback, error) in ctx.expire_rpcs(Instant::now() + after) {
+            callback(node, Err(error), ctx);
+        }
+    }
+
+    /// The messages in the log, in order.
+    fn read_log(node: &mut BroadcastNode, ctx: &mut Context<BroadcastNode>) -> Vec<Value> {
+        let (messages, _) = read(node, ctx, None);
+        messages
+    }
+
+    fn broadcast(node: &mut BroadcastNode, ctx: &mut Context<BroadcastNode>, message: Value) {
+        handle(node, ctx, NodeId::Client(1), Payload::Broadcast { message });
+        ctx.take_sent();
+    }
+
+    #[test]
+    fn test_reconcile_sends_values_only_for_missing_ids() {
+        let (mut node, mut ctx) = node(&[]);
+        broadcast(&mut node, &mut ctx, json!(1));
+        broadcast(&mut node, &mut ctx, json!("x"));
+        let hashed = id_of(&json!("x"));
+
+        let ids = IdSet::from_iter([1, 2]);
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::new(2),
+            Payload::Reconcile { ids },
+        );
+        let sent = take_sent(&mut ctx);
+        assert_eq!(sent.len(), 2);
+        let Payload::ReconcileOk { ids, values, .. } = &sent[0].1 else {
+            panic!("expected the ids the peer lacks, got {sent:?}");
+        };
+        assert_eq!(*ids, IdSet::from_iter([hashed]));
+        assert_eq!(values.get(hashed), Some(json!("x")));
+        let missing = IdSet::from_iter([2]);
+        assert_eq!(sent[1], (NodeId::new(2), Payload::Missing { ids: missing }));
+
+        let ids = IdSet::from_iter([1, 3]);
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::new(2),
+            Payload::Missing { ids },
+        );
+        let sent = take_sent(&mut ctx);
+        let Payload::ReconcileOk { ids, .. } = &sent[0].1 else {
+            panic!("expected the ids asked for, got {sent:?}");
+        };
+        assert_eq!(*ids, IdSet::from_iter([1]));
+    }
+
+    #[test]
+    fn test_gossip_carries_membership_updates() {
+        let (mut node, mut ctx) = node(&["--membership"]);
+        broadcast(&mut node, &mut ctx, json!(1));
+        let suspicion = Update {
+            node: NodeId::new(1),
+            liveness: Liveness::Suspect,
+            incarnation: 0,
+        };
+        let payload = Payload::Gossip {
+            ids_to_see: IdSet::new(),
+            values: Values::default(),
+            stamps: Vec::new(),
+            clock: VectorClock::new(),
+            updates: vec![suspicion],
+        };
+        handle(&mut node, &mut ctx, NodeId::new(0), payload);
+        let sent = take_sent(&mut ctx);
+        let [(_, Payload::GossipOk { updates, .. })] = &sent[..] else {
+            panic!("expected a reply to the gossip, got {sent:?}");
+        };
+        let refutation = Update {
+            node: NodeId::new(1),
+            liveness: Liveness::Alive,
+            incarnation: 1,
+        };
+        assert_eq!(*updates, [refutation]);
+    }
+
+    #[test]
+    fn test_push_pull_sends_only_a_digest() {
+        let (mut node, mut ctx) =
+            node(&["--dissemination", "push-pull", "--push-pull-fanout", "2"]);
+        broadcast(&mut node, &mut ctx, json!(1));
+        node.push_pull(&mut ctx);
+        let digest = node.ids_seen.digest();
+        let sent = take_sent(&mut ctx);
+        assert_eq!(sent.len(), 2);
+        assert!(sent
+            .iter()
+            .all(|(_, payload)| *payload == Payload::Digest { digest }));
+    }
+
+    #[test]
+    fn test_causal_stamps_reach_peers_that_hold_their_ids() {
+        let args = [
+            "--causal",
+            "--topology",
+            "full-mesh",
+            "--gossip-stagger",
+            "0ms",
+        ];
+        let (mut n1, mut ctx1) = cluster_node(1, 1..3, &args);
+        let (mut n2, mut ctx2) = cluster_node(2, 1..3, &args);
+        for (node, ctx) in [(&mut n1, &mut ctx1), (&mut n2, &mut ctx2)] {
+            let topology = HashMap::new();
+            handle(node, ctx, NodeId::Client(1), Payload::Topology { topology });
+            ctx.take_sent();
+        }
+        broadcast(&mut n2, &mut ctx2, json!("V"));
+        broadcast(&mut n1, &mut ctx1, json!("V"));
+        n1.gossip_round(&mut ctx1);
+        deliver(&mut ctx1, &mut n2, &mut ctx2);
+        broadcast(&mut n2, &mut ctx2, json!("W"));
+        n2.gossip_round(&mut ctx2);
+        deliver(&mut ctx2, &mut n1, &mut ctx1);
+        ctx1.take_sent();
+
+        handle(
+            &mut n1,
+            &mut ctx1,
+            NodeId::Client(1),
+            Payload::Read { since: None },
+        );
+        let sent = take_sent(&mut ctx1);
+        let [(_, Payload::ReadOk { messages, .. })] = &sent[..] else {
+            panic!("expected a read reply, got {sent:?}");
+        };
+        assert_eq!(*messages, [json!("V"), json!("W")]);
+    }
+
+    #[test]
+    fn test_acked_nodes_tick_to_report_held_ids_when_collecting_garbage() {
+        let (acked, _) = node(&["--dissemination", "acked"]);
+        assert_eq!(acked.tick_interval(), None);
+        let (mut node, mut ctx) = node(&["--dissemination", "acked", "--gc"]);
+        let interval = node
+            .tick_interval()
+            .expect("Acked nodes tick when collecting garbage");
+        assert!(interval <= Duration::from_millis(100));
+        broadcast(&mut node, &mut ctx, json!(1));
+        node.gossip_round(&mut ctx);
+        let held = Payload::Held {
+            ids: IdSet::from_iter([1]),
+        };
+        let sent = take_sent(&mut ctx);
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(_, payload)| *payload == held));
+    }
+
+    #[test]
+    fn test_reads_since_a_version_return_only_later_messages() {
+        let (mut node, mut ctx) = node(&[]);
+        broadcast(&mut node, &mut ctx, json!(2));
+        broadcast(&mut node, &mut ctx, json!(1));
+        assert_eq!(
+            read(&mut node, &mut ctx, None),
+            (vec![json!(1), json!(2)], None)
+        );
+        assert_eq!(
+            read(&mut node, &mut ctx, Some(0)),
+            (vec![json!(1), json!(2)], Some(2))
+        );
+
+        broadcast(&mut node, &mut ctx, json!(7));
+        let payload = Payload::Gossip {
+            ids_to_see: IdSet::from_iter([1, 3, 4]),
+            values: Values::default(),
+            stamps: Vec::new(),
+            clock: VectorClock::new(),
+            updates: Vec::new(),
+        };
+        handle(&mut node, &mut ctx, NodeId::new(0), payload);
+        ctx.take_sent();
+        assert_eq!(
+            read(&mut node, &mut ctx, Some(2)),
+            (vec![json!(7), json!(3), json!(4)], Some(5))
+        );
+        assert_eq!(read(&mut node, &mut ctx, Some(3)).0, [json!(3), json!(4)]);
+        assert_eq!(read(&mut node, &mut ctx, Some(5)), (Vec::new(), Some(5)));
+    }
+
+    #[test]
+    fn test_gossip_replies_skip_ids_the_sender_has_seen() {
+        let (mut node, mut ctx) = node(&["--topology", "full-mesh"]);
+        let topology = HashMap::new();
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::Client(1),
+            Payload::Topology { topology },
+        );
+        broadcast(&mut node, &mut ctx, json!("x"));
+        broadcast(&mut node, &mut ctx, json!("y"));
+        let gossip = |ids: IdSet| Payload::Gossip {
+            ids_to_see: ids,
+            values: Values::default(),
+            stamps: Vec::new(),
+            clock: VectorClock::new(),
+            updates: Vec::new(),
+        };
+
+        let seen = IdSet::from_iter([id_of(&json!("x")), id_of(&json!("y"))]);
+        handle(&mut node, &mut ctx, NodeId::new(0), gossip(seen));
+        assert!(take_sent(&mut ctx).is_empty());
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::new(0),
+            gossip(IdSet::from_iter([3])),
+        );
+        assert!(take_sent(&mut ctx).is_empty());
+    }
+
+    #[test]
+    fn test_probes_do_not_speed_up_gossip_rounds() {
+        let (mut node, mut ctx) = node(&["--membership", "--gossip-interval", "1s"]);
+        let probe_interval = node.config.membership.probe_interval;
+        node.next_round_at = Instant::now();
+        node.tick(&mut ctx);
+        assert_eq!(node.round, 1);
+        assert_eq!(node.tick_interval(), Some(probe_interval));
+        node.tick(&mut ctx);
+        assert_eq!(node.round, 1);
+    }
+
+    fn acked_node() -> (BroadcastNode, Context<BroadcastNode>) {
+        let (mut node, mut ctx) = node(&["--dissemination", "acked", "--topology", "full-mesh"]);
+        let topology = HashMap::new();
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::Client(1),
+            Payload::Topology { topology },
+        );
+        ctx.take_sent();
+        (node, ctx)
+    }
+
+    #[test]
+    fn test_acked_forwards_go_to_every_peer_but_the_sender() {
+        let (mut node, mut ctx) = acked_node();
+        let payload = Payload::Forward {
+            message: 4,
+            values: Values::default(),
+            stamps: Vec::new(),
+        };
+        handle(&mut node, &mut ctx, NodeId::new(0), payload);
+        let sent = take_sent(&mut ctx);
+        let forward = Payload::Forward {
+            message: 4,
+            values: Values::default(),
+            stamps: Vec::new(),
+        };
+        let ack = Payload::ForwardOk { message: 4 };
+        assert_eq!(sent, [(NodeId::new(0), ack), (NodeId::new(2), forward)]);
+    }
+
+    #[test]
+    fn test_acked_forwards_are_resent_until_acknowledged() {
+        let (mut node, mut ctx) = acked_node();
+        handle(
+            &mut node,
+            &mut ctx,
+            NodeId::Client(1),
+            Payload::Broadcast { message: json!(4) },
+        );
+        let sent = ctx.take_sent();
+        let forwards: Vec<_> = sent
+            .iter()
+            .filter(|message| message.dest != NodeId::Client(1))
+            .collect();
+        assert_eq!(forwards.len(), 2);
+        assert_eq!(ctx.pending_rpcs(), 2);
+
+        expire(&mut node, &mut ctx, Duration::from_millis(250));
+        let resent = ctx.take_sent();
+        let msg_ids = |messages: &[&Sent]| {
+            let mut msg_ids: Vec<_> = messages.iter().map(|message| message.body.msg_id).collect();
+            msg_ids.sort();
+            msg_ids
+        };
+        assert_eq!(
+            msg_ids(&resent.iter().collect::<Vec<_>>()),
+            msg_ids(&forwards)
+        );
+
+        let to_n0 = forwards
+            .iter()
+            .find(|message| message.dest == NodeId::new(0))
+            .expect("n0 is forwarded the message");
+        answer(
+            &mut node,
+            &mut ctx,
+            to_n0,
+            Payload::ForwardOk { message: 4 },
+        );
+        assert_eq!(ctx.pending_rpcs(), 1);
+        assert!(node.unacked[&NodeId::new(0)].is_empty());
+        assert!(node.ids_seen_by_neighbours.has_seen(&NodeId::new(0), 4));
+        assert!(!node.ids_seen_by_neighbours.has_seen(&NodeId::new(2), 4));
+    }
+
+    #[test]
+    fn test_removing_a_peer_cancels_its_forwards() {
+        let (mut node, mut ctx) = node(&["--dissemination", "acked", "--topology", "maelstrom"]);
+        let topology = |neighbours: &[usize]| Payload::Topology {
+            topology: HashMap::from([(
+                NodeId::new(1),
+                neighbours.iter().copied().map(NodeId::new).collect(),
+            )]),
+        };
+        handle(&mut node, &mut ctx, NodeId::Client(1), topology(&[0, 2]));
+        broadcast(&mut node, &mut ctx, json!(4));
+        assert_eq!(ctx.pending_rpcs(), 2);
+
+        handle(&mut node, &mut ctx, NodeId::Client(1), topology(&[0]));
+        ctx.take_sent();
+        assert_eq!(ctx.pending_rpcs(), 1);
+        assert!(!node.unacked.contains_key(&NodeId::new(2)));
+        expire(&mut node, &mut ctx, Duration::from_millis(250));
+        let resent = take_sent(&mut ctx);
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].0, NodeId::new(0));
+    }
+
+    fn total_order_node(args: &[&str]) -> (BroadcastNode, Context<BroadcastNode>) {
+        let args: Vec<_> = ["--dissemination", "total-order"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        node(&args)
+    }
+
+    /// Broadcast `message` and return the `cas` that claims a slot of the log for it.
+    fn claim(node: &mut BroadcastNode, ctx: &mut Context<BroadcastNode>, message: Value) -> Sent {
+        handle(node, ctx, NodeId::Client(1), Payload::Broadcast { message });
+        let mut sent = ctx.take_sent();
+        assert_eq!(sent.len(), 1, "expected a cas, got {sent:?}");
+        let cas = sent.remove(0);
+        assert_eq!(cas.body.payload["type"], "cas");
+        cas
+    }
+
+    #[test]
+    fn test_winning_a_slot_announces_it_and_acknowledges() {
+        let (mut node, mut ctx) = total_order_node(&[]);
+        let cas = claim(&mut node, &mut ctx, json!(5));
+        assert_eq!(cas.dest, KvService::LinKv.node_id());
+        assert_eq!(cas.body.payload["key"], "broadcast-0");
+
+        answer(&mut node, &mut ctx, &cas, json!({"type": "cas_ok"}));
+        let sent = take_sent(&mut ctx);
+        let entries = vec![LogEntry {
+            index: 0,
+            message: json!(5),
+        }];
+        let ordered = Payload::Ordered { entries };
+        assert_eq!(sent.len(), 3);
+        assert!(sent.contains(&(NodeId::new(0), ordered.clone())));
+        assert!(sent.contains(&(NodeId::new(2), ordered)));
+        assert!(sent.contains(&(NodeId::Client(1), Payload::BroadcastOk)));
+    }
+
+    #[test]
+    fn test_losing_a_slot_reads_the_winner_and_claims_the_next() {
+        let (mut node, mut ctx) = total_order_node(&[]);
+        let cas = claim(&mut node, &mut ctx, json!(5));
+        let precondition_failed = json!({"type": "error", "code": 22, "text": "taken"});
+        answer(&mut node, &mut ctx, &cas, precondition_failed);
+        let sent = ctx.take_sent();
+        let [read] = &sent[..] else {
+            panic!("expected a read, got {sent:?}");
+        };
+        assert_eq!(read.body.payload["type"], "read");
+        assert_eq!(read.body.payload["key"], "broadcast-0");
+
+        answer(
+            &mut node,
+            &mut ctx,
+            read,
+            json!({"type": "read_ok", "value": 9}),
+        );
+        let sent = ctx.take_sent();
+        let [cas] = &sent[..] else {
+            panic!("expected a cas, got {sent:?}");
+        };
+        assert_eq!(cas.body.payload["key"], "broadcast-1");
+        answer(&mut node, &mut ctx, cas, json!({"type": "cas_ok"}));
+        ctx.take_sent();
+        let log = read_log(&mut node, &mut ctx);
+        assert_eq!(log, [json!(9), json!(5)]);
+    }
+
+    #[test]
+    fn test_retried_claims_of_the_same_slot_succeed() {
+        let (mut node, mut ctx) = total_order_node(&[]);
+        let cas = claim(&mut node, &mut ctx, json!(5));
+        expire(&mut node, &mut ctx, Duration::from_millis(1500));
+        let sent = ctx.take_sent();
+        let [retry] = &sent[..] else {
+            panic!("expected the cas again, got {sent:?}");
+        };
+        assert_eq!(retry.body.msg_id, cas.body.msg_id);
+        assert_eq!(retry.body.payload, cas.body.payload);
+
+        // The first attempt went through, so the retry finds the slot holding the message.
+        answer(&mut node, &mut ctx, retry, json!({"type": "cas_ok"}));
+        let sent = take_sent(&mut ctx);
+        assert!(sent.contains(&(NodeId::Client(1), Payload::BroadcastOk)));
+    }
+
+    #[test]
+    fn test_claims_that_keep_failing_reach_the_client_as_errors() {
+        let (mut node, mut ctx) = total_order_node(&["--kv-retries", "0"]);
+        let cas = claim(&mut node, &mut ctx, json!(5));
+        let unavailable = json!({"type": "error", "code": 11, "text": "busy"});
+        answer(&mut node, &mut ctx, &cas, unavailable);
+        let sent = ctx.take_sent();
+        let [error] = &sent[..] else {
+            panic!("expected an error, got {sent:?}");
+        };
+        assert_eq!(error.dest, NodeId::Client(1));
+        assert_eq!(error.body.payload["code"], 11);
+
+        claim(&mut node, &mut ctx, json!(6));
+        expire(&mut node, &mut ctx, Duration::from_secs(2));
+        let sent = ctx.take_sent();
+        let [timeout] = &sent[..] else {
+            panic!("expected a timeout, got {sent:?}");
+        };
+        assert_eq!(timeout.body.payload["code"], 0);
+        assert!(read_log(&mut node, &mut ctx).is_empty());
+    }
+}
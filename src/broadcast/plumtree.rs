@@ -0,0 +1,266 @@
+//! Epidemic broadcast trees, after Leitão, Pereira and Rodrigues' Plumtree.
+//!
+//! New messages are pushed eagerly along a spanning tree and announced lazily, in batched
+//! `i_have`s, to every other neighbour. A peer that delivers a duplicate prunes the link it
+//! arrived on out of the tree, and a peer that hears of a message it has not received within
+//! the graft timeout grafts the announcer's link back in and asks it for the message.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use gossip_glomers::message::NodeId;
+
+use crate::{causal::Stamp, id_set::IdSet, message::Payload, value::Values};
+
+/// Payloads to send, and to whom.
+pub type Outgoing = Vec<(NodeId, Payload)>;
+
+#[derive(Debug)]
+pub struct Plumtree {
+    graft_timeout: Duration,
+    eager_peers: HashSet<NodeId>,
+    lazy_peers: HashSet<NodeId>,
+    /// Messages to announce to each lazy peer on the next tick.
+    announcements: HashMap<NodeId, IdSet>,
+    missing: HashMap<usize, Missing>,
+}
+
+/// A message that has been announced but not yet received.
+#[derive(Debug)]
+struct Missing {
+    /// The peers that announced it, in the order to graft them.
+    announcers: VecDeque<NodeId>,
+    deadline: Instant,
+}
+
+impl Plumtree {
+    pub fn new(graft_timeout: Duration) -> Self {
+        Self {
+            graft_timeout,
+            eager_peers: HashSet::new(),
+            lazy_peers: HashSet::new(),
+            announcements: HashMap::new(),
+            missing: HashMap::new(),
+        }
+    }
+
+    /// Start over with every neighbour in the tree, which prunes itself back down as
+    /// duplicates arrive.
+    pub fn set_neighbours(&mut self, neighbours: impl IntoIterator<Item = NodeId>) {
+        self.eager_peers = neighbours.into_iter().collect();
+        self.lazy_peers.clear();
+        self.announcements.clear();
+    }
+
+    /// Forward a message delivered for the first time, either from a client or `from` a peer,
+    /// along with its `values` and `stamps`.
+    pub fn deliver(
+        &mut self,
+        message: usize,
+        values: &Values,
+        stamps: &[Stamp],
+        from: Option<&NodeId>,
+    ) -> Outgoing {
+        self.missing.remove(&message);
+        if let Some(from) = from {
+            self.graft(from);
+        }
+        let is_sender = |peer: &&NodeId| Some(*peer) == from;
+        for peer in self.lazy_peers.iter().filter(|peer| !is_sender(peer)) {
+            let announcements = self.announcements.entry(peer.clone()).or_default();
+            announcements.insert(message);
+        }
+        self.eager_peers
+            .iter()
+            .filter(|peer| !is_sender(peer))
+            .map(|peer| {
+                let values = values.clone();
+                let stamps = stamps.to_vec();
+                let payload = Payload::EagerPush {
+                    message,
+                    values,
+                    stamps,
+                };
+                (peer.clone(), payload)
+            })
+            .collect()
+    }
+
+    /// Prune the link that `message`, already delivered, arrived on.
+    pub fn duplicate(&mut self, message: usize, from: &NodeId) -> Outgoing {
+        self.missing.remove(&message);
+        self.prune(from);
+        vec![(from.clone(), Payload::Prune)]
+    }
+
+    pub fn prune(&mut self, peer: &NodeId) {
+        if self.eager_peers.remove(peer) {
+            self.lazy_peers.insert(peer.clone());
+        }
+    }
+
+    pub fn graft(&mut self, peer: &NodeId) {
+        if self.lazy_peers.remove(peer) {
+            self.eager_peers.insert(peer.clone());
+        }
+    }
+
+    /// Note that `from` has `messages`, which this node has not received.
+    pub fn i_have(
+        &mut self,
+        from: &NodeId,
+        messages: impl IntoIterator<Item = usize>,
+        now: Instant,
+    ) {
+        let deadline = now + self.graft_timeout;
+        for message in messages {
+            let missing = self.missing.entry(message).or_insert_with(|| Missing {
+                announcers: VecDeque::new(),
+                deadline,
+            });
+            if !missing.announcers.contains(from) {
+                missing.announcers.push_back(from.clone());
+            }
+        }
+    }
+
+    /// Announce recent messages to lazy peers, skipping those `has_seen` says they already
+    /// have, and graft the links of announcers whose messages are overdue, forgetting those
+    /// that `is_delivered` says arrived some other way, such as through anti-entropy.
+    pub fn tick(
+        &mut self,
+        now: Instant,
+        has_seen: impl Fn(&NodeId, usize) -> bool,
+        is_delivered: impl Fn(usize) -> bool,
+    ) -> Outgoing {
+        let mut outgoing = Outgoing::new();
+        for (peer, messages) in self.announcements.drain() {
+            let messages: IdSet = messages
+                .iter()
+                .filter(|message| !has_seen(&peer, *message))
+                .collect();
+            if self.lazy_peers.contains(&peer) && !messages.is_empty() {
+                outgoing.push((peer, Payload::IHave { messages }));
+            }
+        }
+        self.missing.retain(|message, _| !is_delivered(*message));
+        let mut grafts: HashMap<NodeId, IdSet> = HashMap::new();
+        for (message, missing) in &mut self.missing {
+            if missing.deadline > now {
+                continue;
+            }
+            let Some(announcer) = missing.announcers.pop_front() else {
+                continue;
+            };
+            grafts
+                .entry(announcer.clone())
+                .or_default()
+                .insert(*message);
+            missing.announcers.push_back(announcer);
+            missing.deadline = now + self.graft_timeout;
+        }
+        for (announcer, messages) in grafts {
+            self.graft(&announcer);
+            outgoing.push((announcer, Payload::Graft { messages }));
+        }
+        outgoing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plumtree(neighbours: &[usize]) -> Plumtree {
+        let mut plumtree = Plumtree::new(Duration::from_millis(100));
+        plumtree.set_neighbours(neighbours.iter().copied().map(NodeId::new));
+        plumtree
+    }
+
+    fn recipients(outgoing: &Outgoing) -> Vec<usize> {
+        let mut recipients: Vec<_> = outgoing
+            .iter()
+            .filter_map(|(peer, _)| peer.node_number())
+            .collect();
+        recipients.sort();
+        recipients
+    }
+
+    #[test]
+    fn test_deliver_pushes_to_eager_peers_except_sender() {
+        let mut plumtree = plumtree(&[1, 2, 3]);
+        let values = Values::default();
+        let outgoing = plumtree.deliver(7, &values, &[], Some(&NodeId::new(1)));
+        assert_eq!(recipients(&outgoing), [2, 3]);
+        let push = Payload::EagerPush {
+            message: 7,
+            values,
+            stamps: Vec::new(),
+        };
+        assert!(outgoing.iter().all(|(_, payload)| *payload == push));
+    }
+
+    #[test]
+    fn test_duplicate_prunes_and_lazy_peers_are_announced_to() {
+        let mut plumtree = plumtree(&[1, 2]);
+        let outgoing = plumtree.duplicate(7, &NodeId::new(1));
+        assert_eq!(outgoing, [(NodeId::new(1), Payload::Prune)]);
+
+        let outgoing = plumtree.deliver(7, &Values::default(), &[], None);
+        assert_eq!(recipients(&outgoing), [2]);
+        let outgoing = plumtree.tick(Instant::now(), |_, _| false, |_| false);
+        let messages = IdSet::from_iter([7]);
+        assert_eq!(outgoing, [(NodeId::new(1), Payload::IHave { messages })]);
+        assert!(plumtree
+            .tick(Instant::now(), |_, _| false, |_| false)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_overdue_announcements_graft_each_announcer_in_turn() {
+        let mut plumtree = plumtree(&[1, 2]);
+        plumtree.prune(&NodeId::new(1));
+        plumtree.prune(&NodeId::new(2));
+        let now = Instant::now();
+        plumtree.i_have(&NodeId::new(1), [7], now);
+        plumtree.i_have(&NodeId::new(2), [7], now);
+        assert!(plumtree.tick(now, |_, _| false, |_| false).is_empty());
+
+        let later = now + Duration::from_millis(100);
+        let messages = IdSet::from_iter([7]);
+        let outgoing = plumtree.tick(later, |_, _| false, |_| false);
+        let graft = Payload::Graft {
+            messages: messages.clone(),
+        };
+        assert_eq!(outgoing, [(NodeId::new(1), graft.clone())]);
+        assert!(plumtree.eager_peers.contains(&NodeId::new(1)));
+
+        let outgoing = plumtree.tick(later + Duration::from_millis(100), |_, _| false, |_| false);
+        assert_eq!(outgoing, [(NodeId::new(2), graft)]);
+
+        plumtree.deliver(7, &Values::default(), &[], Some(&NodeId::new(2)));
+        assert!(plumtree.missing.is_empty());
+    }
+
+    #[test]
+    fn test_messages_received_another_way_are_not_grafted() {
+        let mut plumtree = plumtree(&[1, 2]);
+        plumtree.prune(&NodeId::new(1));
+        let now = Instant::now();
+        plumtree.i_have(&NodeId::new(1), [7, 8], now);
+
+        let later = now + Duration::from_millis(100);
+        let outgoing = plumtree.tick(later, |_, _| false, |message| message == 7);
+        let messages = IdSet::from_iter([8]);
+        assert_eq!(outgoing, [(NodeId::new(1), Payload::Graft { messages })]);
+
+        plumtree.duplicate(8, &NodeId::new(1));
+        assert!(plumtree.missing.is_empty());
+        let much_later = later + Duration::from_millis(100);
+        assert!(plumtree
+            .tick(much_later, |_, _| false, |_| false)
+            .is_empty());
+    }
+}
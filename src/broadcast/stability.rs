@@ -0,0 +1,133 @@
+//! Tracking which ids every node holds.
+//!
+//! Nodes periodically report the ids they hold to each other. Once every other node has
+//! reported holding an id, it is stable: no node can still be missing it, so it need not be
+//! gossiped or remembered per neighbour any more. A node that never reports, such as one that
+//! has crashed, holds stability back.
+//!
+//! Nodes never forget ids, so reports accumulate, and each one only carries the ids that are
+//! not yet stable at the node sending it. An id that becomes stable there before the node has
+//! reported it is reported once more, since the others may not have heard of it yet. If that
+//! report is lost, the id stays unstable at its recipient, which costs only memory.
+
+use std::collections::HashMap;
+
+use gossip_glomers::message::NodeId;
+
+use crate::id_set::IdSet;
+
+#[derive(Debug, Default)]
+pub struct Stability {
+    /// The ids each other node last reported holding that are not yet stable.
+    held: HashMap<NodeId, IdSet>,
+    stable: IdSet,
+    /// The ids this node holds that are not yet stable.
+    unstable: IdSet,
+    /// The ids made stable since this node last reported what it holds.
+    recently_stable: IdSet,
+}
+
+impl Stability {
+    /// Record ids new to this node.
+    pub fn see(&mut self, ids: &IdSet) {
+        self.unstable.union_with(ids);
+    }
+
+    /// The ids this node holds that another node may still be missing.
+    pub fn unstable(&self) -> &IdSet {
+        &self.unstable
+    }
+
+    pub fn stable(&self) -> &IdSet {
+        &self.stable
+    }
+
+    /// The ids to report holding to the other nodes.
+    pub fn take_report(&mut self) -> IdSet {
+        let mut ids = std::mem::take(&mut self.recently_stable);
+        ids.union_with(&self.unstable);
+        ids
+    }
+
+    /// Record the ids that `node` holds, returning those this makes stable given the `others`
+    /// there are besides this node.
+    pub fn report<'a>(
+        &mut self,
+        node: NodeId,
+        ids: &IdSet,
+        others: impl IntoIterator<Item = &'a NodeId>,
+    ) -> IdSet {
+        let held = self.held.entry(node).or_default();
+        held.union_with(&ids.difference(&self.stable));
+        let mut newly_stable = self.unstable.clone();
+        for other in others {
+            let Some(held) = self.held.get(other) else {
+                return IdSet::new();
+            };
+            newly_stable = newly_stable.intersection(held);
+        }
+        if newly_stable.is_empty() {
+            return newly_stable;
+        }
+        self.stable.union_with(&newly_stable);
+        self.recently_stable.union_with(&newly_stable);
+        self.unstable = self.unstable.difference(&newly_stable);
+        for held in self.held.values_mut() {
+            *held = held.difference(&newly_stable);
+        }
+        newly_stable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: impl IntoIterator<Item = usize>) -> IdSet {
+        ids.into_iter().collect()
+    }
+
+    #[test]
+    fn test_ids_become_stable_once_every_other_node_holds_them() {
+        let others = [NodeId::new(1), NodeId::new(2)];
+        let mut stability = Stability::default();
+        stability.see(&ids(0..6));
+
+        let newly_stable = stability.report(NodeId::new(1), &ids(0..4), &others);
+        assert!(newly_stable.is_empty());
+        let newly_stable = stability.report(NodeId::new(2), &ids(2..8), &others);
+        assert_eq!(newly_stable, ids(2..4));
+        assert_eq!(*stability.stable(), ids(2..4));
+        assert_eq!(*stability.unstable(), ids([0, 1, 4, 5]));
+    }
+
+    #[test]
+    fn test_stable_ids_are_not_made_stable_again() {
+        let others = [NodeId::new(1)];
+        let mut stability = Stability::default();
+        stability.see(&ids(0..2));
+        assert_eq!(
+            stability.report(NodeId::new(1), &ids(0..2), &others),
+            ids(0..2)
+        );
+        assert!(stability
+            .report(NodeId::new(1), &ids(0..2), &others)
+            .is_empty());
+        assert!(stability.unstable().is_empty());
+    }
+
+    #[test]
+    fn test_reports_accumulate_and_carry_only_unstable_ids() {
+        let others = [NodeId::new(1), NodeId::new(2)];
+        let mut stability = Stability::default();
+        stability.see(&ids(0..4));
+        assert_eq!(stability.take_report(), ids(0..4));
+
+        stability.report(NodeId::new(1), &ids(0..2), &others);
+        stability.report(NodeId::new(1), &ids(2..4), &others);
+        let newly_stable = stability.report(NodeId::new(2), &ids(1..3), &others);
+        assert_eq!(newly_stable, ids(1..3));
+        assert_eq!(stability.take_report(), ids(0..4));
+        assert_eq!(stability.take_report(), ids([0, 3]));
+    }
+}
@@ -0,0 +1,256 @@
+//! Ways of arranging the nodes into a gossip network.
+//!
+//! Every builder takes the node ids in sorted order and returns each node's neighbours, so all
+//! nodes agree on the shape without exchanging any messages.
+
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+};
+
+use clap::ValueEnum;
+use gossip_glomers::message::NodeId;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+pub type Topology = HashMap<NodeId, HashSet<NodeId>>;
+
+/// How many times to shuffle before settling for a nearly regular random graph.
+const RANDOM_REGULAR_ATTEMPTS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TopologyStrategy {
+    /// The topology sent by Maelstrom.
+    Maelstrom,
+    /// `n0` is connected to every other node, each of which also gossips to the next one round,
+    /// forming a one-way ring among themselves.
+    Hub,
+    /// Every node is connected to the nodes either side of it.
+    Ring,
+    /// The nodes are laid out row by row in a square grid and connected to the nodes above,
+    /// below and beside them.
+    Grid,
+    /// The nodes form a tree in which every node has up to `--tree-arity` children.
+    Tree,
+    /// Every node is connected to `--degree` others chosen at random from `--topology-seed`.
+    RandomRegular,
+    /// Every node is connected to every other node.
+    FullMesh,
+}
+
+fn empty(node_ids: &[NodeId]) -> Topology {
+    node_ids
+        .iter()
+        .map(|node_id| (node_id.clone(), HashSet::new()))
+        .collect()
+}
+
+fn connect(topology: &mut Topology, node_ids: &[NodeId], a: usize, b: usize) {
+    link(topology, node_ids, a, b);
+    link(topology, node_ids, b, a);
+}
+
+/// Make `to` a neighbour of `from`, but not the other way round.
+fn link(topology: &mut Topology, node_ids: &[NodeId], from: usize, to: usize) {
+    if from == to {
+        return;
+    }
+    if let Some(neighbours) = topology.get_mut(&node_ids[from]) {
+        neighbours.insert(node_ids[to].clone());
+    }
+}
+
+pub fn hub(node_ids: &[NodeId]) -> Topology {
+    let mut topology = empty(node_ids);
+    let spokes = node_ids.len().saturating_sub(1);
+    for index in 1..node_ids.len() {
+        connect(&mut topology, node_ids, 0, index);
+        let next = index % spokes + 1;
+        link(&mut topology, node_ids, index, next);
+    }
+    topology
+}
+
+pub fn ring(node_ids: &[NodeId]) -> Topology {
+    let mut topology = empty(node_ids);
+    for index in 0..node_ids.len() {
+        connect(&mut topology, node_ids, index, (index + 1) % node_ids.len());
+    }
+    topology
+}
+
+pub fn grid(node_ids: &[NodeId]) -> Topology {
+    let mut topology = empty(node_ids);
+    let width = (node_ids.len() as f64).sqrt().ceil().max(1.0) as usize;
+    for index in 0..node_ids.len() {
+        if (index + 1) % width != 0 && index + 1 < node_ids.len() {
+            connect(&mut topology, node_ids, index, index + 1);
+        }
+        if index + width < node_ids.len() {
+            connect(&mut topology, node_ids, index, index + width);
+        }
+    }
+    topology
+}
+
+pub fn tree(node_ids: &[NodeId], arity: usize) -> Topology {
+    let mut topology = empty(node_ids);
+    let arity = arity.max(1);
+    for index in 1..node_ids.len() {
+        connect(&mut topology, node_ids, index, (index - 1) / arity);
+    }
+    topology
+}
+
+/// A random graph in which every node has `degree` neighbours.
+///
+/// Stubs are paired at random until no node is paired with itself or twice with the same node.
+/// Should that keep failing, the offending pairs are dropped, leaving a few nodes short. When
+/// `degree * node_ids.len()` is odd one node necessarily has a neighbour fewer.
+pub fn random_regular(node_ids: &[NodeId], degree: usize, seed: u64) -> Topology {
+    let degree = degree.min(node_ids.len().saturating_sub(1));
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stubs: Vec<usize> = (0..node_ids.len())
+        .flat_map(|index| iter::repeat_n(index, degree))
+        .collect();
+    let mut pairs = Vec::new();
+    for attempt in 1..=RANDOM_REGULAR_ATTEMPTS {
+        stubs.shuffle(&mut rng);
+        pairs = stubs
+            .chunks_exact(2)
+            .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+            .collect::<Vec<_>>();
+        let mut distinct = HashSet::new();
+        let is_simple = pairs
+            .iter()
+            .all(|&(a, b)| a != b && distinct.insert((a, b)));
+        if is_simple || attempt == RANDOM_REGULAR_ATTEMPTS {
+            break;
+        }
+    }
+    let mut topology = empty(node_ids);
+    for (a, b) in pairs {
+        connect(&mut topology, node_ids, a, b);
+    }
+    topology
+}
+
+pub fn full_mesh(node_ids: &[NodeId]) -> Topology {
+    node_ids
+        .iter()
+        .map(|node_id| {
+            let mut neighbours: HashSet<NodeId> = node_ids.iter().cloned().collect();
+            neighbours.remove(node_id);
+            (node_id.clone(), neighbours)
+        })
+        .collect()
+}
+
+/// Up to `fanout` nodes other than `node_id` chosen at random, for a round of epidemic gossip
+/// that needs no topology at all.
+pub fn random_peers<'a>(
+    node_ids: &'a [NodeId],
+    node_id: &NodeId,
+    fanout: usize,
+    rng: &mut StdRng,
+) -> Vec<&'a NodeId> {
+    let others: Vec<_> = node_ids.iter().filter(|other| *other != node_id).collect();
+    others.choose_multiple(rng, fanout).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_ids(count: usize) -> Vec<NodeId> {
+        (0..count).map(NodeId::new).collect()
+    }
+
+    fn neighbour_numbers(topology: &Topology, node: usize) -> Vec<usize> {
+        let mut numbers: Vec<_> = topology[&NodeId::new(node)]
+            .iter()
+            .filter_map(NodeId::node_number)
+            .collect();
+        numbers.sort();
+        numbers
+    }
+
+    fn is_symmetric(topology: &Topology) -> bool {
+        topology.iter().all(|(node_id, neighbours)| {
+            neighbours
+                .iter()
+                .all(|neighbour| topology[neighbour].contains(node_id))
+        })
+    }
+
+    #[test]
+    fn test_hub() {
+        let topology = hub(&node_ids(5));
+        assert_eq!(neighbour_numbers(&topology, 0), [1, 2, 3, 4]);
+        assert_eq!(neighbour_numbers(&topology, 1), [0, 2]);
+        assert_eq!(neighbour_numbers(&topology, 4), [0, 1]);
+        assert_eq!(neighbour_numbers(&hub(&node_ids(2)), 1), [0]);
+    }
+
+    #[test]
+    fn test_ring() {
+        let topology = ring(&node_ids(4));
+        assert_eq!(neighbour_numbers(&topology, 0), [1, 3]);
+        assert_eq!(neighbour_numbers(&topology, 2), [1, 3]);
+        assert!(neighbour_numbers(&ring(&node_ids(1)), 0).is_empty());
+    }
+
+    #[test]
+    fn test_grid() {
+        // 0 1 2
+        // 3 4 5
+        // 6
+        let topology = grid(&node_ids(7));
+        assert_eq!(neighbour_numbers(&topology, 0), [1, 3]);
+        assert_eq!(neighbour_numbers(&topology, 4), [1, 3, 5]);
+        assert_eq!(neighbour_numbers(&topology, 5), [2, 4]);
+        assert_eq!(neighbour_numbers(&topology, 6), [3]);
+        assert!(is_symmetric(&topology));
+    }
+
+    #[test]
+    fn test_tree() {
+        let topology = tree(&node_ids(7), 2);
+        assert_eq!(neighbour_numbers(&topology, 0), [1, 2]);
+        assert_eq!(neighbour_numbers(&topology, 1), [0, 3, 4]);
+        assert_eq!(neighbour_numbers(&topology, 6), [2]);
+        assert!(is_symmetric(&topology));
+    }
+
+    #[test]
+    fn test_random_regular() {
+        let ids = node_ids(25);
+        let topology = random_regular(&ids, 4, 7);
+        assert!(topology.values().all(|neighbours| neighbours.len() == 4));
+        assert!(is_symmetric(&topology));
+        assert_eq!(topology, random_regular(&ids, 4, 7));
+    }
+
+    #[test]
+    fn test_full_mesh() {
+        let topology = full_mesh(&node_ids(3));
+        assert_eq!(neighbour_numbers(&topology, 1), [0, 2]);
+    }
+
+    #[test]
+    fn test_random_peers() {
+        let ids = node_ids(10);
+        let me = NodeId::new(3);
+        let peers = random_peers(&ids, &me, 4, &mut StdRng::seed_from_u64(1));
+        assert_eq!(peers.len(), 4);
+        assert!(!peers.contains(&&me));
+        assert_eq!(peers.iter().collect::<HashSet<_>>().len(), 4);
+        assert_eq!(
+            peers,
+            random_peers(&ids, &me, 4, &mut StdRng::seed_from_u64(1))
+        );
+        assert_eq!(
+            random_peers(&ids, &me, 20, &mut StdRng::seed_from_u64(1)).len(),
+            9
+        );
+    }
+}
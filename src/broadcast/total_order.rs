@@ -0,0 +1,109 @@
+//! A log of broadcasts that every node delivers in the same order.
+//!
+//! Slot `n` of the log is the `lin-kv` key [`log_key`]`(n)`, and a node claims it for a message
+//! by compare-and-set, creating the key only if no other node has. Whoever loses the race reads
+//! the winner's message and moves on to the next slot. Nodes announce the slots they win to each
+//! other, and fill any gaps by reading the slots from `lin-kv`.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The `lin-kv` key for slot `index` of the log.
+pub fn log_key(index: usize) -> String {
+    format!("broadcast-{index}")
+}
+
+/// A message in its slot of the log.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LogEntry {
+    pub index: usize,
+    pub message: Value,
+}
+
+/// The ids in each known slot of the log.
+///
+/// A message whose claim timed out after it succeeded may be appended again, so an id is only
+/// delivered from the first slot it is in.
+#[derive(Debug, Default)]
+pub struct Log {
+    /// Slots after the first gap.
+    pending: BTreeMap<usize, usize>,
+    /// The slots up to the first gap, skipping those that repeat an id.
+    delivered: Vec<(usize, usize)>,
+    delivered_ids: HashSet<usize>,
+    /// The first slot that is not known.
+    next_index: usize,
+}
+
+impl Log {
+    /// Record that slot `index` holds `id`, returning the ids this delivers.
+    pub fn insert(&mut self, index: usize, id: usize) -> Vec<usize> {
+        if index >= self.next_index {
+            self.pending.insert(index, id);
+        }
+        let mut newly_delivered = Vec::new();
+        while let Some(id) = self.pending.remove(&self.next_index) {
+            if self.delivered_ids.insert(id) {
+                self.delivered.push((self.next_index, id));
+                newly_delivered.push(id);
+            }
+            self.next_index += 1;
+        }
+        newly_delivered
+    }
+
+    /// Whether `id` is in any known slot.
+    pub fn contains(&self, id: usize) -> bool {
+        self.delivered_ids.contains(&id) || self.pending.values().any(|pending| *pending == id)
+    }
+
+    /// The first slot that is not known, which is the one to claim or read next.
+    pub fn next_index(&self) -> usize {
+        self.next_index
+    }
+
+    /// The slots delivered and the ids in them, in order.
+    pub fn delivered(&self) -> &[(usize, usize)] {
+        &self.delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_delivers_in_slot_order() {
+        let mut log = Log::default();
+        assert!(log.insert(1, 20).is_empty());
+        assert!(log.contains(20));
+        assert_eq!(log.next_index(), 0);
+        assert_eq!(log.insert(0, 10), [10, 20]);
+        assert_eq!(log.insert(2, 30), [30]);
+        assert_eq!(log.delivered(), [(0, 10), (1, 20), (2, 30)]);
+        assert_eq!(log.next_index(), 3);
+    }
+
+    #[test]
+    fn test_insert_ignores_known_slots_and_repeated_ids() {
+        let mut log = Log::default();
+        log.insert(0, 10);
+        assert!(log.insert(0, 10).is_empty());
+        assert!(log.insert(1, 10).is_empty());
+        assert_eq!(log.insert(2, 20), [20]);
+        assert_eq!(log.delivered(), [(0, 10), (2, 20)]);
+        assert_eq!(log.next_index(), 3);
+    }
+
+    #[test]
+    fn test_log_entry_serializes_with_its_index() {
+        let entry = LogEntry {
+            index: 4,
+            message: 7.into(),
+        };
+        let json = serde_json::to_string(&entry).expect("LogEntry is serializable");
+        assert_eq!(json, r#"{"index":4,"message":7}"#);
+    }
+}
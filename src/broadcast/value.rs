@@ -0,0 +1,151 @@
+//! Broadcast values and the ids that stand in for them.
+//!
+//! The node tracks, gossips and reconciles ids rather than values. A non-negative integer below
+//! [`HASHED`] is its own id, which keeps the dense integers Maelstrom broadcasts cheap to store
+//! and send. Any other JSON value gets an id of [`HASHED`] or above from a hash of its canonical
+//! form, so values that are equal but written differently are only delivered once; such values
+//! travel alongside their ids as [`Values`].
+
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::id_set::{IdSet, MAX_ID};
+
+/// The smallest id given to a value that is not its own id.
+pub const HASHED: usize = 1 << (usize::BITS - 1);
+
+/// The id of `value`, which is the value itself for small non-negative integers.
+pub fn id_of(value: &Value) -> usize {
+    let small_integer = value
+        .as_u64()
+        .and_then(|integer| usize::try_from(integer).ok())
+        .filter(|integer| *integer < HASHED);
+    small_integer.unwrap_or_else(|| {
+        // Objects serialize with their keys sorted, so this is the value's canonical form.
+        let canonical = value.to_string();
+        let mut hasher = DefaultHasher::new();
+        canonical.hash(&mut hasher);
+        hashed_id(hasher.finish())
+    })
+}
+
+/// The id for a value with `hash`, kept no larger than an [`IdSet`] can hold.
+fn hashed_id(hash: u64) -> usize {
+    (HASHED | hash as usize).min(MAX_ID)
+}
+
+/// The values of hashed ids, keyed by id.
+///
+/// Serializes as a list of `[id, value]` pairs, because payloads tagged with their `type` lose
+/// track of integer keys in JSON objects.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(from = "Vec<(usize, Value)>", into = "Vec<(usize, Value)>")]
+pub struct Values(BTreeMap<usize, Value>);
+
+impl From<Vec<(usize, Value)>> for Values {
+    fn from(values: Vec<(usize, Value)>) -> Self {
+        Self(values.into_iter().collect())
+    }
+}
+
+impl From<Values> for Vec<(usize, Value)> {
+    fn from(values: Values) -> Self {
+        values.0.into_iter().collect()
+    }
+}
+
+impl Values {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Store `value` if it needs storing, returning its id.
+    pub fn insert(&mut self, value: Value) -> usize {
+        let id = id_of(&value);
+        if id >= HASHED {
+            self.0.entry(id).or_insert(value);
+        }
+        id
+    }
+
+    pub fn extend(&mut self, values: Values) {
+        for (id, value) in values.0 {
+            self.0.entry(id).or_insert(value);
+        }
+    }
+
+    /// The value with `id`, if it is an integer or has been stored.
+    pub fn get(&self, id: usize) -> Option<Value> {
+        match id < HASHED {
+            true => Some(Value::from(id)),
+            false => self.0.get(&id).cloned(),
+        }
+    }
+
+    /// The stored values of `ids`, to send along with them.
+    pub fn for_ids(&self, ids: &IdSet) -> Values {
+        let values = self
+            .0
+            .range(HASHED..)
+            .filter(|(id, _)| ids.contains(**id))
+            .map(|(id, value)| (*id, value.clone()))
+            .collect();
+        Self(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{causal::VectorClock, message::Payload};
+
+    #[test]
+    fn test_hashed_ids_fit_in_an_id_set() {
+        assert_eq!(hashed_id(0), HASHED);
+        assert_eq!(hashed_id(u64::MAX), MAX_ID);
+        let mut ids = IdSet::new();
+        assert!(ids.insert(hashed_id(u64::MAX)));
+    }
+
+    #[test]
+    fn test_small_integers_are_their_own_ids() {
+        let mut values = Values::default();
+        assert_eq!(values.insert(json!(42)), 42);
+        assert!(values.is_empty());
+        assert_eq!(values.get(42), Some(json!(42)));
+    }
+
+    #[test]
+    fn test_other_values_are_deduplicated_by_canonical_form() {
+        let mut values = Values::default();
+        let record: Value = serde_json::from_str(r#"{"b":[1,2],"a":"x"}"#).expect("valid JSON");
+        let reordered: Value = serde_json::from_str(r#"{"a":"x","b":[1,2]}"#).expect("valid JSON");
+        let id = values.insert(record.clone());
+        assert!(id >= HASHED);
+        assert_eq!(values.insert(reordered), id);
+        assert_ne!(values.insert(json!("x")), id);
+        assert_ne!(id_of(&json!(-1)), id_of(&json!(1)));
+        assert_eq!(values.get(id), Some(record));
+
+        let ids: IdSet = [1, id].into_iter().collect();
+        let sent = values.for_ids(&ids);
+        assert_eq!(sent.0.len(), 1);
+        let payload = Payload::Gossip {
+            ids_to_see: ids,
+            values: sent,
+            stamps: Vec::new(),
+            clock: VectorClock::new(),
+            updates: Vec::new(),
+        };
+        let json = serde_json::to_string(&payload).expect("Payload is serializable");
+        let received: Payload = serde_json::from_str(&json).expect("Payload is deserializable");
+        assert_eq!(received, payload);
+    }
+}
@@ -0,0 +1,88 @@
+//! Versions of the ids a node has seen, for reading only those seen since a version.
+//!
+//! A version counts the ids seen, in the order they were. Rather than remember that order id by
+//! id, ids that are seen one after another and are also consecutive are kept as a single run,
+//! along with the version it starts at, so ids that arrive in sorted batches take little space.
+
+use std::ops::Range;
+
+use crate::id_set::IdSet;
+
+#[derive(Debug, Default)]
+pub struct Versions {
+    /// Runs of consecutive ids, each with the version before its first id was seen.
+    runs: Vec<(usize, Range<usize>)>,
+    version: usize,
+}
+
+impl Versions {
+    /// The ids in `ids`, seen in sorted order.
+    pub fn from_ids(ids: &IdSet) -> Self {
+        let mut versions = Self::default();
+        versions.extend(ids);
+        versions
+    }
+
+    /// The number of ids seen so far.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Record that `id` was seen, after every id seen before it.
+    pub fn push(&mut self, id: usize) {
+        self.push_range(id..id + 1);
+    }
+
+    /// Record that `ids` were seen, in sorted order, after every id seen before them.
+    pub fn extend(&mut self, ids: &IdSet) {
+        ids.ranges().for_each(|range| self.push_range(range));
+    }
+
+    fn push_range(&mut self, range: Range<usize>) {
+        let len = range.len();
+        match self.runs.last_mut() {
+            Some((_, run)) if run.end == range.start => run.end = range.end,
+            _ => self.runs.push((self.version, range)),
+        }
+        self.version += len;
+    }
+
+    /// The ids seen after `version`, in the order they were.
+    pub fn since(&self, version: usize) -> impl Iterator<Item = usize> + '_ {
+        let first = self
+            .runs
+            .partition_point(|(start, _)| *start <= version)
+            .saturating_sub(1);
+        self.runs[first..].iter().flat_map(move |(start, run)| {
+            let skip = version.saturating_sub(*start);
+            run.clone().skip(skip)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consecutive_ids_share_a_run() {
+        let mut versions = Versions::from_ids(&IdSet::from_iter([1, 2, 3, 7]));
+        versions.push(8);
+        versions.push(4);
+        assert_eq!(versions.runs, [(0, 1..4), (3, 7..9), (5, 4..5)]);
+        assert_eq!(versions.version(), 6);
+    }
+
+    #[test]
+    fn test_since_reads_from_within_a_run() {
+        let mut versions = Versions::default();
+        versions.extend(&IdSet::from_iter([10, 11, 12]));
+        versions.push(5);
+        assert!(versions.since(0).eq([10, 11, 12, 5]));
+        assert!(versions.since(2).eq([12, 5]));
+        assert!(versions.since(3).eq([5]));
+        assert_eq!(versions.since(4).count(), 0);
+        assert_eq!(versions.since(9).count(), 0);
+        assert_eq!(Versions::default().since(0).count(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{num::ParseIntError, time::Duration};
+
+use clap::{Args, Parser, ValueEnum};
+
+/// The command line of every binary: logging options plus those of the node itself.
+///
+/// Maelstrom starts nodes without arguments, so every option can also be set through an
+/// environment variable prefixed with `GOSSIP_GLOMERS_`.
+#[derive(Debug, Clone, Parser)]
+pub struct Cli<C: Args> {
+    #[command(flatten)]
+    pub log: LogConfig,
+    #[command(flatten)]
+    pub node: C,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct LogConfig {
+    /// Which logs to write to stderr, as a level or a filter such as `warn,gossip_glomers=info`.
+    #[arg(long, env = "GOSSIP_GLOMERS_LOG", default_value = "info")]
+    pub log_filter: String,
+    #[arg(long, env = "GOSSIP_GLOMERS_LOG_FORMAT", value_enum, default_value_t = LogFormat::Full)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Json,
+}
+
+/// For nodes that have nothing to configure.
+#[derive(Debug, Clone, Default, Args)]
+pub struct NoConfig {}
+
+#[derive(Debug, Clone, Args)]
+pub struct GossipConfig {
+    /// How often to gossip, e.g. `100ms` or `1s`.
+    #[arg(
+        long = "gossip-interval",
+        env = "GOSSIP_GLOMERS_GOSSIP_INTERVAL",
+        default_value = "100ms",
+        value_parser = parse_duration
+    )]
+    pub interval: Duration,
+    /// How long each node waits per node number before gossiping, so that nodes don't all
+    /// gossip at the same instant.
+    #[arg(
+        long = "gossip-stagger",
+        env = "GOSSIP_GLOMERS_GOSSIP_STAGGER",
+        default_value = "1us",
+        value_parser = parse_duration
+    )]
+    pub stagger: Duration,
+    /// The most neighbours to gossip with each round, or all of them if unset.
+    #[arg(long, env = "GOSSIP_GLOMERS_FANOUT")]
+    pub fanout: Option<usize>,
+}
+
+impl GossipConfig {
+    pub fn stagger(&self, node_number: usize) -> Duration {
+        let node_number = u32::try_from(node_number).unwrap_or(u32::MAX);
+        self.stagger.saturating_mul(node_number)
+    }
+
+    /// The peers to gossip with in `round`: all of them, or a window of `fanout` peers that
+    /// moves along `peers` from one round to the next.
+    pub fn peers_for_round<'a, T>(
+        &self,
+        round: usize,
+        peers: &'a [T],
+    ) -> impl Iterator<Item = &'a T> {
+        let fanout = self.fanout.unwrap_or(peers.len()).min(peers.len());
+        let start = match peers.len() {
+            0 => 0,
+            len => round.wrapping_mul(fanout) % len,
+        };
+        peers.iter().cycle().skip(start).take(fanout)
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MembershipConfig {
+    /// How often to ping a peer to check that it is alive.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_PROBE_INTERVAL",
+        default_value = "200ms",
+        value_parser = parse_duration
+    )]
+    pub probe_interval: Duration,
+    /// How long to wait for a peer to answer a ping.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_PROBE_TIMEOUT",
+        default_value = "100ms",
+        value_parser = parse_duration
+    )]
+    pub probe_timeout: Duration,
+    /// How long a suspect peer has to show that it is alive before it is declared dead.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_SUSPECT_TIMEOUT",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    pub suspect_timeout: Duration,
+    /// How many other peers to ask to ping a peer that did not answer.
+    #[arg(long, env = "GOSSIP_GLOMERS_INDIRECT_PROBES", default_value_t = 3)]
+    pub indirect_probes: usize,
+    /// The most membership updates to piggyback on a single message.
+    #[arg(long, env = "GOSSIP_GLOMERS_MAX_PIGGYBACK", default_value_t = 6)]
+    pub max_piggyback: usize,
+    /// Each update is piggybacked this many times the log of the cluster size.
+    #[arg(
+        long,
+        env = "GOSSIP_GLOMERS_RETRANSMIT_MULTIPLIER",
+        default_value_t = 3
+    )]
+    pub retransmit_multiplier: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseDurationError {
+    #[error("Invalid duration: {0}")]
+    InvalidAmount(#[from] ParseIntError),
+    #[error("Unknown duration unit `{0}`, expected `us`, `ms` or `s`")]
+    UnknownUnit(String),
+}
+
+/// Parse a duration such as `250us`, `100ms` or `2s`; a bare number is taken as milliseconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, ParseDurationError> {
+    let duration = duration.trim();
+    let unit_start = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(unit_start);
+    let amount: u64 = amount.parse()?;
+    let duration = match unit.trim() {
+        "us" => Duration::from_micros(amount),
+        "s" => Duration::from_secs(amount),
+        "" | "ms" => Duration::from_millis(amount),
+        unit => return Err(ParseDurationError::UnknownUnit(unit.into())),
+    };
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse_duration("100ms"), Ok(Duration::from_millis(100)));
+        assert_eq!(parse_duration("100"), Ok(Duration::from_millis(100)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert!(parse_duration("2h").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn test_peers_for_round_rotates() {
+        let mut cli = Cli::<GossipConfig>::parse_from(["node"]);
+        let peers = [1, 2, 3, 4, 5];
+        let all: Vec<_> = cli.node.peers_for_round(7, &peers).collect();
+        assert_eq!(all, [&1, &2, &3, &4, &5]);
+
+        cli.node.fanout = Some(2);
+        let rounds: Vec<Vec<_>> = (0..3)
+            .map(|round| cli.node.peers_for_round(round, &peers).collect())
+            .collect();
+        assert_eq!(rounds, [vec![&1, &2], vec![&3, &4], vec![&5, &1]]);
+    }
+
+    #[test]
+    fn test_cli_defaults() {
+        let cli = Cli::<GossipConfig>::parse_from(["node"]);
+        assert_eq!(cli.log.log_filter, "info");
+        assert_eq!(cli.node.interval, Duration::from_millis(100));
+        assert_eq!(cli.node.fanout, None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashSet, fmt, time::Instant};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::{self, Error},
+    message::{Message, NodeId},
+    rpc::{Callback, Expired, PendingRequest, PendingRequests, RetryPolicy},
+};
+
+#[derive(Debug)]
+pub struct Context<N> {
+    node_id: NodeId,
+    node_ids: HashSet<NodeId>,
+    msg_id: usize,
+    pending_requests: PendingRequests<N>,
+    /// Set to keep sent messages for [`Context::take_sent`] rather than writing them to stdout.
+    outbox: Option<Vec<Message<Value>>>,
+}
+
+impl<N> Context<N> {
+    pub fn new(node_id: NodeId, node_ids: HashSet<NodeId>, msg_id: usize) -> Self {
+        let pending_requests = PendingRequests::default();
+        Self {
+            node_id,
+            node_ids,
+            msg_id,
+            pending_requests,
+            outbox: None,
+        }
+    }
+
+    /// Keep the messages sent instead of writing them to stdout, so that tests can inspect them.
+    pub fn with_outbox(self) -> Self {
+        let outbox = Some(Vec::new());
+        Self { outbox, ..self }
+    }
+
+    /// The messages sent since the last call, if this context has an outbox.
+    pub fn take_sent(&mut self) -> Vec<Message<Value>> {
+        self.outbox.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn transmit<P: Serialize + fmt::Debug>(&mut self, message: Message<P>) {
+        let Some(outbox) = &mut self.outbox else {
+            message.send();
+            return;
+        };
+        let Message { src, dest, body } = message;
+        let payload =
+            serde_json::to_value(body.payload).expect("Payloads can always be serialized");
+        outbox.push(Message::new(
+            src,
+            dest,
+            body.msg_id,
+            body.in_reply_to,
+            payload,
+        ));
+    }
+
+    pub fn node_id(&self) -> &NodeId {
+        &self.node_id
+    }
+
+    pub fn node_ids(&self) -> &HashSet<NodeId> {
+        &self.node_ids
+    }
+
+    pub fn next_msg_id(&mut self) -> usize {
+        let msg_id = self.msg_id;
+        self.msg_id += 1;
+        msg_id
+    }
+
+    /// Send `payload` to `dest`, returning the message's `msg_id`.
+    pub fn send<P: Serialize + fmt::Debug>(
+        &mut self,
+        dest: impl Into<NodeId>,
+        payload: P,
+    ) -> usize {
+        self.reply(dest, None, payload)
+    }
+
+    /// Send `payload` to `dest` in reply to `in_reply_to`, returning the message's `msg_id`.
+    pub fn reply<P: Serialize + fmt::Debug>(
+        &mut self,
+        dest: impl Into<NodeId>,
+        in_reply_to: impl Into<Option<usize>>,
+        payload: P,
+    ) -> usize {
+        let msg_id = self.next_msg_id();
+        let message = Message::new(self.node_id.clone(), dest, msg_id, in_reply_to, payload);
+        self.transmit(message);
+        msg_id
+    }
+
+    pub fn reply_error(
+        &mut self,
+        dest: impl Into<NodeId>,
+        in_reply_to: impl Into<Option<usize>>,
+        error: Error,
+    ) {
+        let payload = error::Payload::from(error);
+        self.reply(dest, in_reply_to, payload);
+    }
+
+    /// Send `payload` to `dest` and call `callback` with its reply.
+    ///
+    /// The request is retransmitted with the same `msg_id` according to `policy`; if every
+    /// attempt times out, `callback` receives [`Error::Timeout`]. Returns the request's `msg_id`.
+    pub fn rpc<P, R, F>(
+        &mut self,
+        dest: impl Into<NodeId>,
+        payload: P,
+        policy: RetryPolicy,
+        callback: F,
+    ) -> usize
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+        F: FnOnce(&mut N, Result<R, Error>, &mut Context<N>) + 'static,
+    {
+        let dest = dest.into();
+        let msg_id = self.next_msg_id();
+        let payload = serde_json::to_value(payload).expect("Payloads can always be serialized");
+        let callback: Callback<N> = Box::new(move |node, reply, ctx| {
+            let reply = reply.and_then(|payload| {
+                serde_json::from_value::<R>(payload)
+                    .map_err(|error| Error::MalformedRequest(error.to_string()))
+            });
+            callback(node, reply, ctx)
+        });
+        let request = PendingRequest::new(dest.clone(), payload.clone(), policy, callback);
+        self.pending_requests.insert(msg_id, request);
+        let message = Message::new(self.node_id.clone(), dest, msg_id, None, payload);
+        self.transmit(message);
+        msg_id
+    }
+
+    /// Stop waiting for a reply to `msg_id`, returning whether it was still pending.
+    pub fn cancel_rpc(&mut self, msg_id: usize) -> bool {
+        self.pending_requests.remove(msg_id).is_some()
+    }
+
+    pub fn pending_rpcs(&self) -> usize {
+        self.pending_requests.len()
+    }
+
+    pub(crate) fn take_callback(&mut self, in_reply_to: usize) -> Option<Callback<N>> {
+        let request = self.pending_requests.remove(in_reply_to)?;
+        Some(request.into_callback())
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.pending_requests.next_deadline()
+    }
+
+    /// Retransmit overdue requests, returning the callbacks of those that have run out of retries.
+    pub fn expire_rpcs(&mut self, now: Instant) -> Vec<(Callback<N>, Error)> {
+        let mut expired = Vec::new();
+        for request in self.pending_requests.expire(now) {
+            match request {
+                Expired::Retry {
+                    msg_id,
+                    dest,
+                    payload,
+                } => {
+                    let message = Message::new(self.node_id.clone(), dest, msg_id, None, payload);
+                    self.transmit(message);
+                }
+                Expired::GiveUp {
+                    msg_id,
+                    dest,
+                    callback,
+                } => {
+                    let text = format!("no reply from {dest:?} to request {msg_id}");
+                    expired.push((callback, Error::Timeout(text)));
+                }
+            }
+        }
+        expired
+    }
+}
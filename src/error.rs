@@ -1,9 +1,138 @@
-#[derive(Debug, thiserror::Error)]
+use serde::{Deserialize, Serialize};
+
+/// The errors defined by the Maelstrom protocol, each carrying a human-readable description.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
 pub enum Error {
-    #[error("Did not receive `init` request")]
-    Initialisation,
-    #[error("Server already initialised")]
-    AlreadyInitialised,
-    #[error("Server should not receive a `{0}` request")]
-    InvalidRequest(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Node not found: {0}")]
+    NodeNotFound(String),
+    #[error("Not supported: {0}")]
+    NotSupported(String),
+    #[error("Temporarily unavailable: {0}")]
+    TemporarilyUnavailable(String),
+    #[error("Malformed request: {0}")]
+    MalformedRequest(String),
+    #[error("Crashed: {0}")]
+    Crash(String),
+    #[error("Aborted: {0}")]
+    Abort(String),
+    #[error("Key does not exist: {0}")]
+    KeyDoesNotExist(String),
+    #[error("Key already exists: {0}")]
+    KeyAlreadyExists(String),
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+    #[error("Transaction conflict: {0}")]
+    TxnConflict(String),
+    #[error("Error {code}: {text}")]
+    Custom { code: u32, text: String },
+}
+
+impl Error {
+    pub fn new(code: u32, text: impl Into<String>) -> Self {
+        let text = text.into();
+        match code {
+            0 => Self::Timeout(text),
+            1 => Self::NodeNotFound(text),
+            10 => Self::NotSupported(text),
+            11 => Self::TemporarilyUnavailable(text),
+            12 => Self::MalformedRequest(text),
+            13 => Self::Crash(text),
+            14 => Self::Abort(text),
+            20 => Self::KeyDoesNotExist(text),
+            21 => Self::KeyAlreadyExists(text),
+            22 => Self::PreconditionFailed(text),
+            30 => Self::TxnConflict(text),
+            code => Self::Custom { code, text },
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Timeout(_) => 0,
+            Self::NodeNotFound(_) => 1,
+            Self::NotSupported(_) => 10,
+            Self::TemporarilyUnavailable(_) => 11,
+            Self::MalformedRequest(_) => 12,
+            Self::Crash(_) => 13,
+            Self::Abort(_) => 14,
+            Self::KeyDoesNotExist(_) => 20,
+            Self::KeyAlreadyExists(_) => 21,
+            Self::PreconditionFailed(_) => 22,
+            Self::TxnConflict(_) => 30,
+            Self::Custom { code, .. } => *code,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Timeout(text)
+            | Self::NodeNotFound(text)
+            | Self::NotSupported(text)
+            | Self::TemporarilyUnavailable(text)
+            | Self::MalformedRequest(text)
+            | Self::Crash(text)
+            | Self::Abort(text)
+            | Self::KeyDoesNotExist(text)
+            | Self::KeyAlreadyExists(text)
+            | Self::PreconditionFailed(text)
+            | Self::TxnConflict(text)
+            | Self::Custom { text, .. } => text,
+        }
+    }
+
+    /// Whether the request is known not to have taken effect.
+    ///
+    /// Timeouts and crashes are indefinite: the operation may or may not have happened.
+    /// Maelstrom treats codes it does not define as indefinite too.
+    pub fn is_definite(&self) -> bool {
+        !matches!(
+            self,
+            Self::Timeout(_) | Self::Crash(_) | Self::Custom { .. }
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Payload {
+    Error { code: u32, text: String },
+}
+
+impl From<Error> for Payload {
+    fn from(error: Error) -> Self {
+        let code = error.code();
+        let text = error.text().to_owned();
+        Self::Error { code, text }
+    }
+}
+
+impl From<Payload> for Error {
+    fn from(payload: Payload) -> Self {
+        let Payload::Error { code, text } = payload;
+        Self::new(code, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip() {
+        for code in [0, 1, 10, 11, 12, 13, 14, 20, 21, 22, 30, 1000] {
+            let error = Error::new(code, "text");
+            assert_eq!(error.code(), code);
+            assert_eq!(error.text(), "text");
+        }
+    }
+
+    #[test]
+    fn test_serialize_payload() {
+        let error = Error::KeyDoesNotExist("k0".into());
+        let payload = Payload::from(error);
+        let json = serde_json::to_string(&payload).expect("Payload is serializable");
+        assert_eq!(json, r#"{"type":"error","code":20,"text":"k0"}"#);
+    }
 }
@@ -12,10 +12,7 @@ impl GrowOnlyCounter {
     }
 
     pub fn add_to_count(&mut self, index: usize, delta: u32) -> Option<()> {
-        let maybe_current = self.counts.get_mut(index);
-        let Some(current) = maybe_current else {
-            return None;
-        };
+        let current = self.counts.get_mut(index)?;
         *current += delta;
         Some(())
     }
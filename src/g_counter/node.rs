@@ -1,176 +1,110 @@
-use std::{collections::HashSet, thread, time::Duration};
+use std::{thread, time::Duration};
 
+use gossip_glomers::{
+    config::GossipConfig, context::Context, error::Error, message::NodeId, node::Node,
+    rpc::RetryPolicy,
+};
 use tracing::{error, info};
 
 use crate::{
     counter::GrowOnlyCounter,
-    message::{Message, NodeId, Payload},
+    message::{Message, Payload},
 };
 
 #[derive(Debug)]
-pub enum Node {
-    Uninitialised {
-        msg_id: usize,
-    },
-    Initialised {
-        msg_id: usize,
-        node_id: NodeId,
-        node_ids: HashSet<NodeId>,
-        counter: GrowOnlyCounter,
-    },
+pub struct CounterNode {
+    config: GossipConfig,
+    /// This node's slot in the counter, which is its position among the sorted node ids.
+    index: usize,
+    neighbour_ids: Vec<NodeId>,
+    round: usize,
+    counter: GrowOnlyCounter,
 }
 
-impl Node {
-    pub fn new() -> Self {
-        Node::Uninitialised { msg_id: 0 }
+impl Node for CounterNode {
+    type Payload = Payload;
+    type Config = GossipConfig;
+
+    fn init(config: GossipConfig, ctx: &mut Context<Self>) -> Self {
+        let mut node_ids: Vec<_> = ctx.node_ids().iter().cloned().collect();
+        node_ids.sort();
+        let index = node_ids
+            .iter()
+            .position(|node_id| node_id == ctx.node_id())
+            .expect("A node is always one of the node ids");
+        let counter = GrowOnlyCounter::new(node_ids.len());
+        let mut neighbour_ids = node_ids;
+        neighbour_ids.remove(index);
+        Self {
+            config,
+            index,
+            neighbour_ids,
+            round: 0,
+            counter,
+        }
     }
-}
 
-impl Node {
-    pub fn handle(self, request: Message) -> Self {
-        info!(target: "Received message", message = ?request);
-        match self {
-            Node::Uninitialised { msg_id } => match request.body.payload {
-                Payload::Init { node_id, node_ids } => {
-                    handle_init_request(msg_id, node_id, node_ids, request.src, request.body.msg_id)
-                }
-                payload => {
-                    error!(target: "invalid payload", node_type = "Uninitialised", payload = ?payload);
-                    Node::Uninitialised { msg_id }
-                }
-            },
-            Node::Initialised {
-                msg_id,
-                node_id,
-                node_ids,
-                mut counter,
-            } => match request.body.payload {
-                Payload::Add { delta } => {
-                    let Some(()) = counter.add_to_count(node_id.id_number(), delta) else {
-                        todo!();
-                    };
-                    let node = Node::Initialised {
-                        msg_id,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        counter,
-                    };
-                    let response_payload = Payload::AddOk;
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::Read => {
-                    let value = counter.sum();
-                    let node = Node::Initialised {
-                        msg_id,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        counter,
-                    };
-                    let response_payload = Payload::ReadOk { value };
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::Gossip { other_counts } => {
-                    counter.update_counts(&other_counts);
-                    let updated_counts = counter.counts().to_owned();
-                    let node = Node::Initialised {
-                        msg_id,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        counter,
-                    };
-                    let response_payload = Payload::GossipOk { updated_counts };
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::GossipOk { updated_counts } => {
-                    counter.update_counts(&updated_counts);
-                    Node::Initialised {
-                        msg_id,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        counter,
-                    }
-                }
-                payload => {
-                    error!(target: "invalid payload", node_type = "Initialised", payload = ?payload);
-                    Node::Initialised {
-                        msg_id,
-                        node_id,
-                        node_ids,
-                        counter,
-                    }
-                }
-            },
+    fn handle(&mut self, request: Message, ctx: &mut Context<Self>) {
+        let src = request.src;
+        let in_reply_to = request.body.msg_id;
+        match request.body.payload {
+            Payload::Add { delta } => {
+                let Some(()) = self.counter.add_to_count(self.index, delta) else {
+                    let text = format!("no slot {} in the counter", self.index);
+                    ctx.reply_error(src, in_reply_to, Error::Crash(text));
+                    return;
+                };
+                ctx.reply(src, in_reply_to, Payload::AddOk);
+            }
+            Payload::Read => {
+                let value = self.counter.sum();
+                ctx.reply(src, in_reply_to, Payload::ReadOk { value });
+            }
+            Payload::Gossip { other_counts } => {
+                self.counter.update_counts(&other_counts);
+                let updated_counts = self.counter.counts().to_owned();
+                ctx.reply(src, in_reply_to, Payload::GossipOk { updated_counts });
+            }
+            Payload::GossipOk { updated_counts } => {
+                self.counter.update_counts(&updated_counts);
+            }
+            payload => {
+                error!(target: "invalid payload", node_type = "Initialised", payload = ?payload);
+                let text = format!("unexpected payload {payload:?}");
+                ctx.reply_error(src, in_reply_to, Error::NotSupported(text));
+            }
         }
     }
-    pub fn gossip(&self) {
-        let Node::Initialised {
-            msg_id: _,
-            node_id,
-            node_ids,
-            counter,
-        } = self
-        else {
-            return;
-        };
-        let id_number = node_id.id_number();
-        let duration = Duration::from_micros(id_number as u64);
-        thread::sleep(duration);
-        node_ids
-            .iter()
-            .filter(|neighbour_id| neighbour_id != &node_id)
+
+    fn tick(&mut self, ctx: &mut Context<Self>) {
+        let id_number = ctx.node_id().node_number().unwrap_or_default();
+        thread::sleep(self.config.stagger(id_number));
+        let policy = RetryPolicy::new(self.config.interval);
+        self.config
+            .peers_for_round(self.round, &self.neighbour_ids)
             .for_each(|neighbour_id| {
-                let counts = counter.counts().to_owned();
+                let counts = self.counter.counts().to_owned();
                 let payload = Payload::Gossip {
                     other_counts: counts,
                 };
-                let request =
-                    Message::new(node_id.clone(), neighbour_id.clone(), None, None, payload);
-                request.send();
-            })
+                ctx.rpc(neighbour_id.clone(), payload, policy, handle_gossip_reply);
+            });
+        self.round += 1;
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(self.config.interval)
     }
 }
 
-fn handle_init_request(
-    msg_id: usize,
-    node_id: NodeId,
-    node_ids: HashSet<NodeId>,
-    dest: NodeId,
-    in_reply_to: impl Into<Option<usize>>,
-) -> Node {
-    let node_count = node_ids.len();
-    let counter = GrowOnlyCounter::new(node_count);
-    let node = Node::Initialised {
-        msg_id: msg_id + 1,
-        node_id: node_id.clone(),
-        node_ids,
-        counter,
-    };
-    let response_payload = Payload::InitOk;
-    let response = Message::new(node_id, dest, msg_id, in_reply_to, response_payload);
-    response.send();
-    node
+fn handle_gossip_reply(
+    node: &mut CounterNode,
+    reply: Result<Payload, Error>,
+    _: &mut Context<CounterNode>,
+) {
+    match reply {
+        Ok(Payload::GossipOk { updated_counts }) => node.counter.update_counts(&updated_counts),
+        Ok(payload) => error!(target: "invalid reply", payload = ?payload),
+        Err(error) => info!(target: "Gossip failed", error = %error),
+    }
 }
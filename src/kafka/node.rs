@@ -1,134 +1,54 @@
-use std::collections::HashSet;
-
-use tracing::{error, info};
+use gossip_glomers::{config::NoConfig, context::Context, error::Error, node::Node};
+use tracing::error;
 
 use crate::{
     log::Logs,
-    message::{Message, NodeId, Payload},
+    message::{Message, Payload},
 };
 
 #[derive(Debug)]
-pub enum Node {
-    Uninitialised {
-        msg_id: usize,
-    },
-    Initialised {
-        msg_id: usize,
-        node_id: NodeId,
-        node_ids: HashSet<NodeId>,
-        log: Logs,
-    },
+pub struct KafkaNode {
+    log: Logs,
 }
 
-impl Node {
-    pub fn new() -> Self {
-        Node::Uninitialised { msg_id: 0 }
+impl Node for KafkaNode {
+    type Payload = Payload;
+    type Config = NoConfig;
+
+    fn init(_: NoConfig, _: &mut Context<Self>) -> Self {
+        let log = Logs::default();
+        Self { log }
     }
 
-    pub fn handle(self, request: Message) -> Self {
-        info!(target: "Received message", message = ?request);
-        match self {
-            Node::Uninitialised { msg_id } => match request.body.payload {
-                Payload::Init { node_id, node_ids } => {
-                    handle_init_request(msg_id, node_id, node_ids, request.src, request.body.msg_id)
-                }
-                payload => {
-                    error!(target: "invalid payload", node_type = "Uninitialised", payload = ?payload);
-                    Node::Uninitialised { msg_id }
-                }
-            },
-            Node::Initialised {
-                msg_id,
-                node_id,
-                node_ids,
-                mut log,
-            } => match request.body.payload {
-                Payload::Send { key, msg } => {
-                    let offset = log.append_message(key, msg);
-                    let node = Node::Initialised {
-                        msg_id: msg_id + 1,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        log,
-                    };
-                    let response_payload = Payload::SendOk { offset };
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::Poll { offsets } => {
-                    let messages = log.since_offset(offsets).as_messages();
-                    let node = Node::Initialised {
-                        msg_id: msg_id + 1,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        log,
-                    };
-                    let response_payload = Payload::PollOk { msgs: messages };
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::CommitOffsets { offsets } => {
-                    log.commit_offsets(offsets);
-                    let node = Node::Initialised {
-                        msg_id: msg_id + 1,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        log,
-                    };
-                    let response_payload = Payload::CommitOffsetsOk;
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                Payload::ListCommittedOffsets { keys } => {
-                    let offsets = log.list_committed_offsets(keys);
-                    let node = Node::Initialised {
-                        msg_id: msg_id + 1,
-                        node_id: node_id.clone(),
-                        node_ids,
-                        log,
-                    };
-                    let response_payload = Payload::ListCommittedOffsetsOk { offsets };
-                    let response = Message::new(
-                        node_id,
-                        request.src,
-                        msg_id,
-                        request.body.msg_id,
-                        response_payload,
-                    );
-                    response.send();
-                    node
-                }
-                payload => {
-                    error!(target: "invalid payload", node_type = "Initialised", payload = ?payload);
-                    Node::Initialised {
-                        msg_id,
-                        node_id,
-                        node_ids,
-                        log,
-                    }
-                }
-            },
+    fn handle(&mut self, request: Message, ctx: &mut Context<Self>) {
+        let src = request.src;
+        let in_reply_to = request.body.msg_id;
+        match request.body.payload {
+            Payload::Send { key, msg } => {
+                let offset = self.log.append_message(key, msg);
+                let response_payload = Payload::SendOk { offset };
+                ctx.reply(src, in_reply_to, response_payload);
+            }
+            Payload::Poll { offsets } => {
+                let messages = self.log.since_offset(offsets).as_messages();
+                let response_payload = Payload::PollOk { msgs: messages };
+                ctx.reply(src, in_reply_to, response_payload);
+            }
+            Payload::CommitOffsets { offsets } => {
+                self.log.commit_offsets(offsets);
+                let response_payload = Payload::CommitOffsetsOk;
+                ctx.reply(src, in_reply_to, response_payload);
+            }
+            Payload::ListCommittedOffsets { keys } => {
+                let offsets = self.log.list_committed_offsets(keys);
+                let response_payload = Payload::ListCommittedOffsetsOk { offsets };
+                ctx.reply(src, in_reply_to, response_payload);
+            }
+            payload => {
+                error!(target: "invalid payload", node_type = "Initialised", payload = ?payload);
+                let text = format!("unexpected payload {payload:?}");
+                ctx.reply_error(src, in_reply_to, Error::NotSupported(text));
+            }
         }
     }
 
@@ -160,26 +80,6 @@ impl Node {
     // }
 }
 
-fn handle_init_request(
-    msg_id: usize,
-    node_id: NodeId,
-    node_ids: HashSet<NodeId>,
-    dest: NodeId,
-    in_reply_to: impl Into<Option<usize>>,
-) -> Node {
-    let log = Logs::default();
-    let node = Node::Initialised {
-        msg_id: msg_id + 1,
-        node_id: node_id.clone(),
-        node_ids,
-        log,
-    };
-    let response_payload = Payload::InitOk;
-    let response = Message::new(node_id, dest, msg_id, in_reply_to, response_payload);
-    response.send();
-    node
-}
-
 // fn handle_echo_request(
 //     msg_id: usize,
 //     node_id: NodeId,
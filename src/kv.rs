@@ -0,0 +1,276 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{context::Context, error::Error, message::NodeId, rpc::RetryPolicy};
+
+/// The key-value stores that Maelstrom runs alongside the nodes under test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KvService {
+    /// Linearizable.
+    LinKv,
+    /// Sequentially consistent.
+    SeqKv,
+    /// Last-write-wins, which may lose writes and return stale values.
+    LwwKv,
+}
+
+impl KvService {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LinKv => "lin-kv",
+            Self::SeqKv => "seq-kv",
+            Self::LwwKv => "lww-kv",
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId::Service(self.name().into())
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Payload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+/// How a compare-and-set that reached the service turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasOutcome {
+    Swapped,
+    /// The key held a value other than `from`.
+    PreconditionFailed,
+    /// The key was missing and `create_if_not_exists` was not set.
+    KeyDoesNotExist,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KvClient {
+    service: KvService,
+    policy: RetryPolicy,
+}
+
+impl KvClient {
+    pub fn new(service: KvService) -> Self {
+        let policy = RetryPolicy::default();
+        Self { service, policy }
+    }
+
+    pub fn with_policy(self, policy: RetryPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    pub fn service(&self) -> KvService {
+        self.service
+    }
+
+    /// Read `key`, which the callback receives as `None` if it does not exist.
+    pub fn read<N, K, V, F>(&self, ctx: &mut Context<N>, key: K, callback: F)
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+        F: FnOnce(&mut N, Result<Option<V>, Error>, &mut Context<N>) + 'static,
+    {
+        let key = to_value(key);
+        let payload = Payload::Read { key };
+        ctx.rpc(
+            self.service.node_id(),
+            payload,
+            self.policy,
+            move |node, reply, ctx| {
+                let value = match reply {
+                    Ok(Payload::ReadOk { value }) => from_value(value).map(Some),
+                    Ok(payload) => Err(unexpected_reply(payload)),
+                    Err(Error::KeyDoesNotExist(_)) => Ok(None),
+                    Err(error) => Err(error),
+                };
+                callback(node, value, ctx)
+            },
+        );
+    }
+
+    pub fn write<N, K, V, F>(&self, ctx: &mut Context<N>, key: K, value: V, callback: F)
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<(), Error>, &mut Context<N>) + 'static,
+    {
+        let key = to_value(key);
+        let value = to_value(value);
+        let payload = Payload::Write { key, value };
+        ctx.rpc(
+            self.service.node_id(),
+            payload,
+            self.policy,
+            move |node, reply, ctx| {
+                let result = match reply {
+                    Ok(Payload::WriteOk) => Ok(()),
+                    Ok(payload) => Err(unexpected_reply(payload)),
+                    Err(error) => Err(error),
+                };
+                callback(node, result, ctx)
+            },
+        );
+    }
+
+    /// Set `key` to `to` if it currently holds `from`, or create it when it is missing and
+    /// `create_if_not_exists` is set.
+    pub fn cas<N, K, V, F>(
+        &self,
+        ctx: &mut Context<N>,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        callback: F,
+    ) where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<CasOutcome, Error>, &mut Context<N>) + 'static,
+    {
+        let key = to_value(key);
+        let from = to_value(from);
+        let to = to_value(to);
+        let payload = Payload::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+        ctx.rpc(
+            self.service.node_id(),
+            payload,
+            self.policy,
+            move |node, reply, ctx| {
+                let outcome = match reply {
+                    Ok(Payload::CasOk) => Ok(CasOutcome::Swapped),
+                    Ok(payload) => Err(unexpected_reply(payload)),
+                    Err(Error::PreconditionFailed(_)) => Ok(CasOutcome::PreconditionFailed),
+                    Err(Error::KeyDoesNotExist(_)) => Ok(CasOutcome::KeyDoesNotExist),
+                    Err(error) => Err(error),
+                };
+                callback(node, outcome, ctx)
+            },
+        );
+    }
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("Keys and values can always be serialized")
+}
+
+fn from_value<V: DeserializeOwned>(value: Value) -> Result<V, Error> {
+    serde_json::from_value(value).map_err(|error| Error::MalformedRequest(error.to_string()))
+}
+
+fn unexpected_reply(payload: Payload) -> Error {
+    let text = format!("unexpected reply {payload:?}");
+    Error::MalformedRequest(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Answer the only request sent through `ctx` with the error `code`, as the runtime would.
+    fn fail<N>(node: &mut N, ctx: &mut Context<N>, code: u32) {
+        let sent = ctx.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, KvService::LinKv.node_id());
+        let msg_id = sent[0].body.msg_id.expect("Requests have a msg_id");
+        let callback = ctx.take_callback(msg_id).expect("The request is pending");
+        callback(node, Err(Error::new(code, "no")), ctx);
+    }
+
+    fn context<N>() -> Context<N> {
+        Context::new(NodeId::new(1), HashSet::new(), 0).with_outbox()
+    }
+
+    #[test]
+    fn test_cas_omits_create_if_not_exists_unless_set() {
+        let payload = Payload::Cas {
+            key: "counter".into(),
+            from: 1.into(),
+            to: 2.into(),
+            create_if_not_exists: false,
+        };
+        let json = serde_json::to_string(&payload).expect("Payload is serializable");
+        assert_eq!(json, r#"{"type":"cas","key":"counter","from":1,"to":2}"#);
+
+        let payload = Payload::Cas {
+            key: "counter".into(),
+            from: 0.into(),
+            to: 1.into(),
+            create_if_not_exists: true,
+        };
+        let json = serde_json::to_string(&payload).expect("Payload is serializable");
+        assert_eq!(
+            json,
+            r#"{"type":"cas","key":"counter","from":0,"to":1,"create_if_not_exists":true}"#
+        );
+    }
+
+    #[test]
+    fn test_reads_of_missing_keys_are_none() {
+        let mut ctx = context();
+        let mut value = None;
+        KvClient::new(KvService::LinKv).read(&mut ctx, "counter", |value, result, _| {
+            *value = Some(result);
+        });
+        fail(&mut value, &mut ctx, 20);
+        assert!(matches!(value, Some(Ok(None::<u64>))));
+
+        KvClient::new(KvService::LinKv).read(&mut ctx, "counter", |value, result, _| {
+            *value = Some(result);
+        });
+        fail(&mut value, &mut ctx, 11);
+        assert!(matches!(value, Some(Err(Error::TemporarilyUnavailable(_)))));
+    }
+
+    #[test]
+    fn test_cas_failures_are_outcomes() {
+        let mut ctx = context();
+        let kv = KvClient::new(KvService::LinKv);
+        let mut outcome = None;
+        kv.cas(&mut ctx, "counter", 1, 2, false, |outcome, result, _| {
+            *outcome = Some(result);
+        });
+        fail(&mut outcome, &mut ctx, 20);
+        assert!(matches!(outcome, Some(Ok(CasOutcome::KeyDoesNotExist))));
+
+        kv.cas(&mut ctx, "counter", 1, 2, false, |outcome, result, _| {
+            *outcome = Some(result);
+        });
+        fail(&mut outcome, &mut ctx, 22);
+        assert!(matches!(outcome, Some(Ok(CasOutcome::PreconditionFailed))));
+
+        kv.cas(&mut ctx, "counter", 1, 2, false, |outcome, result, _| {
+            *outcome = Some(result);
+        });
+        fail(&mut outcome, &mut ctx, 11);
+        assert!(matches!(
+            outcome,
+            Some(Err(Error::TemporarilyUnavailable(_)))
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod kv;
+pub mod membership;
+pub mod message;
+pub mod node;
+pub mod rpc;
+pub mod runtime;
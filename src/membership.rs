@@ -0,0 +1,356 @@
+//! SWIM-style failure detection and membership.
+//!
+//! Every probe interval a node pings one peer, in a shuffled round-robin order. If no reply
+//! arrives within the probe timeout it asks a few other peers to ping it on its behalf, and if
+//! none of them hears back either the peer becomes suspect. Suspects that don't refute the
+//! suspicion within the suspect timeout are declared dead. Changes spread by piggybacking on
+//! the pings and their replies, and on whatever else the node sends its peers, and each
+//! member's incarnation number orders the claims made about it: a member refutes a suspicion of
+//! itself by announcing that it is alive under a higher incarnation.
+//!
+//! [`Membership`] only keeps the state; the node sends the pings and reports the outcomes.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
+};
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::MembershipConfig, message::NodeId};
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liveness {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// A claim about a member, piggybacked on pings, gossip and their replies.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Update {
+    pub node: NodeId,
+    pub liveness: Liveness,
+    pub incarnation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Member {
+    liveness: Liveness,
+    incarnation: u32,
+    since: Instant,
+}
+
+#[derive(Debug)]
+pub struct Membership {
+    config: MembershipConfig,
+    node_id: NodeId,
+    incarnation: u32,
+    members: HashMap<NodeId, Member>,
+    probe_order: Vec<NodeId>,
+    next_probe: usize,
+    next_probe_at: Instant,
+    /// How many indirect pings of each member are still outstanding.
+    indirect_probes: HashMap<NodeId, usize>,
+    /// Updates to piggyback, with how many more times to send each.
+    updates: VecDeque<(Update, usize)>,
+}
+
+impl Membership {
+    /// Start out believing that every other node is alive.
+    pub fn new(config: MembershipConfig, node_id: NodeId, node_ids: &HashSet<NodeId>) -> Self {
+        let now = Instant::now();
+        let members = node_ids
+            .iter()
+            .filter(|member| member.is_node() && **member != node_id)
+            .map(|member| {
+                let member_state = Member {
+                    liveness: Liveness::Alive,
+                    incarnation: 0,
+                    since: now,
+                };
+                (member.clone(), member_state)
+            })
+            .collect();
+        Self {
+            config,
+            node_id,
+            incarnation: 0,
+            members,
+            probe_order: Vec::new(),
+            next_probe: 0,
+            next_probe_at: now,
+            indirect_probes: HashMap::new(),
+            updates: VecDeque::new(),
+        }
+    }
+
+    pub fn incarnation(&self) -> u32 {
+        self.incarnation
+    }
+
+    /// What this node believes of `member`; itself and strangers are alive.
+    pub fn liveness(&self, member: &NodeId) -> Liveness {
+        self.members
+            .get(member)
+            .map_or(Liveness::Alive, |member| member.liveness)
+    }
+
+    pub fn is_dead(&self, member: &NodeId) -> bool {
+        self.liveness(member) == Liveness::Dead
+    }
+
+    pub fn view(&self) -> impl Iterator<Item = (&NodeId, Liveness)> {
+        self.members
+            .iter()
+            .map(|(member, state)| (member, state.liveness))
+    }
+
+    /// The member to ping, if a probe is due. Dead members are still pinged so that they are
+    /// noticed once a partition heals.
+    pub fn next_probe(&mut self, now: Instant) -> Option<NodeId> {
+        if now < self.next_probe_at || self.members.is_empty() {
+            return None;
+        }
+        self.next_probe_at = now + self.config.probe_interval;
+        if self.next_probe >= self.probe_order.len() {
+            self.probe_order = self.members.keys().cloned().collect();
+            self.probe_order.shuffle(&mut rand::thread_rng());
+            self.next_probe = 0;
+        }
+        let target = self.probe_order[self.next_probe].clone();
+        self.next_probe += 1;
+        Some(target)
+    }
+
+    /// A ping of `target` went unanswered, so pick up to `--indirect-probes` members that are
+    /// not dead to ping it on this node's behalf. If there are none, `target` becomes suspect.
+    pub fn direct_probe_failed(&mut self, target: &NodeId, now: Instant) -> Vec<NodeId> {
+        let mut helpers: Vec<NodeId> = self
+            .members
+            .iter()
+            .filter(|(member, state)| *member != target && state.liveness != Liveness::Dead)
+            .map(|(member, _)| member.clone())
+            .collect();
+        helpers.shuffle(&mut rand::thread_rng());
+        helpers.truncate(self.config.indirect_probes);
+        match helpers.is_empty() {
+            true => self.suspect(target, now),
+            false => {
+                self.indirect_probes.insert(target.clone(), helpers.len());
+            }
+        }
+        helpers
+    }
+
+    /// A member asked to ping `target` did not report back; once none of them has, `target`
+    /// becomes suspect.
+    pub fn indirect_probe_failed(&mut self, target: &NodeId, now: Instant) {
+        let Some(remaining) = self.indirect_probes.get_mut(target) else {
+            return;
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.indirect_probes.remove(target);
+            self.suspect(target, now);
+        }
+    }
+
+    /// `target` answered a ping, directly or through another member.
+    pub fn probe_succeeded(&mut self, target: &NodeId, incarnation: u32, now: Instant) {
+        self.indirect_probes.remove(target);
+        let Some(member) = self.members.get(target) else {
+            return;
+        };
+        if member.liveness != Liveness::Alive || incarnation > member.incarnation {
+            let incarnation = incarnation.max(member.incarnation);
+            self.set(target.clone(), Liveness::Alive, incarnation, now);
+        }
+    }
+
+    pub fn suspect(&mut self, target: &NodeId, now: Instant) {
+        let Some(member) = self.members.get(target) else {
+            return;
+        };
+        if member.liveness == Liveness::Alive {
+            self.set(target.clone(), Liveness::Suspect, member.incarnation, now);
+        }
+    }
+
+    /// Declare dead the suspects that have not refuted the suspicion in time.
+    pub fn expire_suspects(&mut self, now: Instant) {
+        let expired: Vec<_> = self
+            .members
+            .iter()
+            .filter(|(_, state)| state.liveness == Liveness::Suspect)
+            .filter(|(_, state)| now.duration_since(state.since) >= self.config.suspect_timeout)
+            .map(|(member, state)| (member.clone(), state.incarnation))
+            .collect();
+        for (member, incarnation) in expired {
+            self.set(member, Liveness::Dead, incarnation, now);
+        }
+    }
+
+    /// Apply updates piggybacked on a message from another member.
+    pub fn apply(&mut self, updates: Vec<Update>, now: Instant) {
+        for update in updates {
+            if update.node == self.node_id {
+                self.refute(&update);
+                continue;
+            }
+            let Some(member) = self.members.get(&update.node) else {
+                continue;
+            };
+            let is_newer = update.incarnation > member.incarnation;
+            let is_news = match update.liveness {
+                Liveness::Alive => is_newer,
+                Liveness::Suspect => {
+                    is_newer
+                        || (member.liveness == Liveness::Alive
+                            && update.incarnation == member.incarnation)
+                }
+                Liveness::Dead => {
+                    member.liveness != Liveness::Dead && update.incarnation >= member.incarnation
+                }
+            };
+            if is_news {
+                self.set(update.node, update.liveness, update.incarnation, now);
+            }
+        }
+    }
+
+    /// Updates to piggyback on the next message.
+    pub fn updates_to_send(&mut self) -> Vec<Update> {
+        let count = self.updates.len().min(self.config.max_piggyback);
+        let mut updates = Vec::with_capacity(count);
+        for _ in 0..count {
+            let Some((update, remaining)) = self.updates.pop_front() else {
+                break;
+            };
+            updates.push(update.clone());
+            if remaining > 1 {
+                self.updates.push_back((update, remaining - 1));
+            }
+        }
+        updates
+    }
+
+    fn refute(&mut self, update: &Update) {
+        if update.liveness != Liveness::Alive && update.incarnation >= self.incarnation {
+            self.incarnation = update.incarnation + 1;
+            let refutation = Update {
+                node: self.node_id.clone(),
+                liveness: Liveness::Alive,
+                incarnation: self.incarnation,
+            };
+            self.enqueue(refutation);
+        }
+    }
+
+    fn set(&mut self, node: NodeId, liveness: Liveness, incarnation: u32, now: Instant) {
+        let member = Member {
+            liveness,
+            incarnation,
+            since: now,
+        };
+        self.members.insert(node.clone(), member);
+        let update = Update {
+            node,
+            liveness,
+            incarnation,
+        };
+        self.enqueue(update);
+    }
+
+    /// Queue `update` to be piggybacked a number of times that grows with the log of the
+    /// cluster size, replacing any older update about the same member.
+    fn enqueue(&mut self, update: Update) {
+        self.updates
+            .retain(|(queued, _)| queued.node != update.node);
+        let cluster_size = self.members.len() + 1;
+        let transmissions = self.config.retransmit_multiplier * (cluster_size.ilog2() as usize + 1);
+        self.updates.push_back((update, transmissions));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Cli;
+
+    fn membership(node: usize, nodes: usize) -> Membership {
+        let cli = Cli::<MembershipConfig>::parse_from(["node"]);
+        let node_ids = (0..nodes).map(NodeId::new).collect();
+        Membership::new(cli.node, NodeId::new(node), &node_ids)
+    }
+
+    #[test]
+    fn test_probes_every_member_in_turn() {
+        let mut membership = membership(0, 4);
+        let now = Instant::now();
+        let interval = membership.config.probe_interval;
+        let probed: HashSet<_> = (0..3)
+            .filter_map(|round| membership.next_probe(now + interval * round))
+            .collect();
+        assert_eq!(probed.len(), 3);
+        assert!(!probed.contains(&NodeId::new(0)));
+        assert_eq!(membership.next_probe(now + interval * 2), None);
+    }
+
+    #[test]
+    fn test_unanswered_probes_lead_to_suspicion_then_death() {
+        let mut membership = membership(0, 5);
+        let target = NodeId::new(1);
+        let now = Instant::now();
+        let helpers = membership.direct_probe_failed(&target, now);
+        assert_eq!(helpers.len(), 3);
+        assert!(!helpers.contains(&target));
+        for _ in helpers {
+            assert_eq!(membership.liveness(&target), Liveness::Alive);
+            membership.indirect_probe_failed(&target, now);
+        }
+        assert_eq!(membership.liveness(&target), Liveness::Suspect);
+
+        membership.expire_suspects(now + Duration::from_millis(1));
+        assert_eq!(membership.liveness(&target), Liveness::Suspect);
+        membership.expire_suspects(now + membership.config.suspect_timeout);
+        assert!(membership.is_dead(&target));
+
+        membership.probe_succeeded(&target, 0, now);
+        assert_eq!(membership.liveness(&target), Liveness::Alive);
+    }
+
+    #[test]
+    fn test_members_refute_suspicion_with_a_higher_incarnation() {
+        let mut accuser = membership(0, 3);
+        let mut accused = membership(1, 3);
+        let now = Instant::now();
+        accuser.suspect(&NodeId::new(1), now);
+        accused.apply(accuser.updates_to_send(), now);
+        assert_eq!(accused.incarnation(), 1);
+
+        let refutation = accused.updates_to_send();
+        assert!(refutation.contains(&Update {
+            node: NodeId::new(1),
+            liveness: Liveness::Alive,
+            incarnation: 1,
+        }));
+        accuser.apply(refutation, now);
+        assert_eq!(accuser.liveness(&NodeId::new(1)), Liveness::Alive);
+
+        // A stale suspicion cannot override the refutation.
+        let stale = Update {
+            node: NodeId::new(1),
+            liveness: Liveness::Suspect,
+            incarnation: 0,
+        };
+        accuser.apply(vec![stale], now);
+        assert_eq!(accuser.liveness(&NodeId::new(1)), Liveness::Alive);
+    }
+}
@@ -1,26 +1,22 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fmt,
-};
+use std::{fmt, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 use tracing::info;
-use uuid::Uuid;
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
-pub struct Message {
+pub struct Message<P> {
     pub src: NodeId,
     pub dest: NodeId,
-    pub body: Body,
+    pub body: Body<P>,
 }
 
-impl Message {
+impl<P> Message<P> {
     pub fn new(
         src: impl Into<NodeId>,
         dest: impl Into<NodeId>,
         msg_id: impl Into<Option<usize>>,
         in_reply_to: impl Into<Option<usize>>,
-        payload: Payload,
+        payload: P,
     ) -> Self {
         let body = Body::new(msg_id, in_reply_to, payload);
         Self {
@@ -29,14 +25,16 @@ impl Message {
             body,
         }
     }
+}
 
+impl<P: Serialize + fmt::Debug> Message<P> {
     pub fn send(self) {
         println!("{}", &self);
         info!(target: "Sent message", message = ?self);
     }
 }
 
-impl fmt::Display for Message {
+impl<P: Serialize> fmt::Display for Message<P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // fmt::Error does not support transmitting any information about an error other than that the error occurred.
         let string = serde_json::to_string(self).map_err(|_| fmt::Error)?;
@@ -45,18 +43,18 @@ impl fmt::Display for Message {
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
-pub struct Body {
+pub struct Body<P> {
     pub msg_id: Option<usize>,
     pub in_reply_to: Option<usize>,
     #[serde(flatten)]
-    pub payload: Payload,
+    pub payload: P,
 }
 
-impl Body {
+impl<P> Body<P> {
     pub fn new(
         msg_id: impl Into<Option<usize>>,
         in_reply_to: impl Into<Option<usize>>,
-        payload: Payload,
+        payload: P,
     ) -> Self {
         Self {
             msg_id: msg_id.into(),
@@ -66,63 +64,166 @@ impl Body {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
-#[serde(rename_all = "snake_case", tag = "type")]
-pub enum Payload {
-    Init {
-        node_id: NodeId,
-        node_ids: HashSet<NodeId>,
-    },
-    InitOk,
-    Echo {
-        echo: String,
-    },
-    EchoOk {
-        echo: String,
-    },
-    Generate,
-    GenerateOk {
-        id: Uuid,
-    },
-    Broadcast {
-        message: usize,
-    },
-    BroadcastOk,
-    Read,
-    ReadOk {
-        messages: HashSet<usize>,
-    },
-    Topology {
-        topology: HashMap<NodeId, HashSet<NodeId>>,
-    },
-    TopologyOk,
-    Gossip {
-        ids_to_see: HashSet<usize>,
-    },
-    GossipOk {
-        ids_to_see: HashSet<usize>,
-    },
-}
-
+/// The address of a participant in a Maelstrom test: `n3`, `c12` or a service like `lin-kv`.
 #[derive(Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Clone)]
-pub struct NodeId(Box<str>);
+#[serde(try_from = "String", into = "String")]
+pub enum NodeId {
+    /// A node under test.
+    Node(u32),
+    /// A client issuing requests on behalf of the workload.
+    Client(u32),
+    /// One of Maelstrom's built-in services.
+    Service(Box<str>),
+}
 
 impl NodeId {
     pub fn new(id: usize) -> Self {
-        let id = format!("n{}", id);
-        Self(id.into())
+        let id = u32::try_from(id).expect("Node numbers fit in a u32");
+        Self::Node(id)
+    }
+
+    pub fn service(name: &str) -> Result<Self, ParseNodeIdError> {
+        match name.parse()? {
+            node_id @ Self::Service(_) => Ok(node_id),
+            _ => Err(ParseNodeIdError::NotAService(name.into())),
+        }
+    }
+
+    /// The number of a node under test, or `None` for clients and services.
+    pub fn node_number(&self) -> Option<usize> {
+        match self {
+            Self::Node(id) => Some(*id as usize),
+            Self::Client(_) | Self::Service(_) => None,
+        }
+    }
+
+    pub fn is_node(&self) -> bool {
+        matches!(self, Self::Node(_))
     }
-    pub fn id_number(&self) -> usize {
-        self.0
+
+    pub fn is_client(&self) -> bool {
+        matches!(self, Self::Client(_))
+    }
+
+    pub fn is_service(&self) -> bool {
+        matches!(self, Self::Service(_))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Node(id) => write!(f, "n{}", id),
+            Self::Client(id) => write!(f, "c{}", id),
+            Self::Service(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseNodeIdError {
+    #[error("Node id is empty")]
+    Empty,
+    #[error("`{0}` is not a valid node id")]
+    Invalid(String),
+    #[error("`{0}` is not a service")]
+    NotAService(String),
+}
+
+impl FromStr for NodeId {
+    type Err = ParseNodeIdError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseNodeIdError::Invalid(id.into());
+        let numbered = |prefix| {
+            id.strip_prefix(prefix)
+                .filter(|number: &&str| number.starts_with(|c: char| c.is_ascii_digit()))
+        };
+        if id.is_empty() {
+            Err(ParseNodeIdError::Empty)
+        } else if let Some(number) = numbered('n') {
+            parse_number(number).map(Self::Node).ok_or_else(invalid)
+        } else if let Some(number) = numbered('c') {
+            parse_number(number).map(Self::Client).ok_or_else(invalid)
+        } else if is_service_name(id) {
+            Ok(Self::Service(id.into()))
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+/// Parse a number without leading zeros, so that every id has exactly one spelling.
+fn parse_number(number: &str) -> Option<u32> {
+    let is_canonical = number == "0" || !number.starts_with('0');
+    let is_digits = number.chars().all(|c| c.is_ascii_digit());
+    if is_canonical && is_digits {
+        number.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_service_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
             .chars()
-            .nth(1)
-            .expect("There will always be 2 characters")
-            .to_digit(10)
-            .expect("The second character will always be a digit") as usize
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+impl TryFrom<String> for NodeId {
+    type Error = ParseNodeIdError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        id.parse()
+    }
+}
+
+impl From<NodeId> for String {
+    fn from(node_id: NodeId) -> Self {
+        node_id.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multi_digit_ids() {
+        assert_eq!("n24".parse(), Ok(NodeId::Node(24)));
+        assert_eq!("c103".parse(), Ok(NodeId::Client(103)));
+        assert_eq!(NodeId::new(10).node_number(), Some(10));
+        assert_eq!(NodeId::new(10).to_string(), "n10");
+    }
+
+    #[test]
+    fn test_parse_services() {
+        let node_id: NodeId = "lin-kv".parse().expect("lin-kv is a service");
+        assert!(node_id.is_service());
+        assert_eq!(node_id.node_number(), None);
+        assert_eq!(node_id.to_string(), "lin-kv");
+        assert!(NodeId::service("n1").is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_ids() {
+        for id in ["", "n01", "N1", "-kv", "lin kv"] {
+            assert!(id.parse::<NodeId>().is_err(), "{id} should be invalid");
+        }
+        assert!(serde_json::from_str::<NodeId>(r#""n 1""#).is_err());
     }
 
-    pub fn is_hub_node(&self) -> bool {
-        let id_number = self.id_number();
-        id_number == 0
+    #[test]
+    fn test_serde_round_trip() {
+        let node_ids = vec![
+            NodeId::new(12),
+            NodeId::Client(3),
+            NodeId::Service("seq-kv".into()),
+        ];
+        let json = serde_json::to_string(&node_ids).expect("Node ids are serializable");
+        assert_eq!(json, r#"["n12","c3","seq-kv"]"#);
+        let parsed: Vec<NodeId> = serde_json::from_str(&json).expect("Node ids are deserializable");
+        assert_eq!(parsed, node_ids);
     }
 }
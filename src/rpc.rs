@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{context::Context, error::Error, message::NodeId};
+
+/// Called with the reply to a request, or with [`Error::Timeout`] once every attempt has timed out.
+pub type Callback<N> = Box<dyn FnOnce(&mut N, Result<Value, Error>, &mut Context<N>)>;
+
+/// How long to wait for a reply, and how to retransmit a request while none arrives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    /// How many times to retransmit before giving up, or `None` to retry forever.
+    pub retries: Option<u32>,
+    /// Each retransmission waits this many times longer than the previous attempt.
+    pub backoff: f64,
+    pub max_timeout: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            retries: Some(0),
+            backoff: 1.0,
+            max_timeout: timeout,
+        }
+    }
+
+    pub fn with_retries(self, retries: impl Into<Option<u32>>) -> Self {
+        let retries = retries.into();
+        Self { retries, ..self }
+    }
+
+    pub fn with_backoff(self, backoff: f64, max_timeout: Duration) -> Self {
+        Self {
+            backoff,
+            max_timeout,
+            ..self
+        }
+    }
+
+    fn timeout(&self, attempt: u32) -> Duration {
+        let attempt = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let seconds = self.timeout.as_secs_f64() * self.backoff.powi(attempt);
+        // Past the cap the backoff can grow too large for a `Duration`.
+        Duration::try_from_secs_f64(seconds)
+            .map_or(self.max_timeout, |timeout| timeout.min(self.max_timeout))
+    }
+
+    fn can_retry(&self, attempt: u32) -> bool {
+        self.retries.is_none_or(|retries| attempt < retries)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        let timeout = Duration::from_secs(1);
+        Self::new(timeout)
+    }
+}
+
+pub struct PendingRequest<N> {
+    dest: NodeId,
+    payload: Value,
+    deadline: Instant,
+    attempt: u32,
+    policy: RetryPolicy,
+    callback: Callback<N>,
+}
+
+impl<N> PendingRequest<N> {
+    pub fn new(dest: NodeId, payload: Value, policy: RetryPolicy, callback: Callback<N>) -> Self {
+        let deadline = Instant::now() + policy.timeout(0);
+        Self {
+            dest,
+            payload,
+            deadline,
+            attempt: 0,
+            policy,
+            callback,
+        }
+    }
+
+    pub fn into_callback(self) -> Callback<N> {
+        self.callback
+    }
+}
+
+impl<N> fmt::Debug for PendingRequest<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingRequest")
+            .field("dest", &self.dest)
+            .field("payload", &self.payload)
+            .field("deadline", &self.deadline)
+            .field("attempt", &self.attempt)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What to do with a request whose deadline has passed.
+pub enum Expired<N> {
+    Retry {
+        msg_id: usize,
+        dest: NodeId,
+        payload: Value,
+    },
+    GiveUp {
+        msg_id: usize,
+        dest: NodeId,
+        callback: Callback<N>,
+    },
+}
+
+/// Requests awaiting a reply, keyed by the `msg_id` they were sent with.
+#[derive(Debug)]
+pub struct PendingRequests<N>(HashMap<usize, PendingRequest<N>>);
+
+impl<N> Default for PendingRequests<N> {
+    fn default() -> Self {
+        let pending = HashMap::new();
+        Self(pending)
+    }
+}
+
+impl<N> PendingRequests<N> {
+    pub fn insert(&mut self, msg_id: usize, request: PendingRequest<N>) {
+        self.0.insert(msg_id, request);
+    }
+
+    pub fn remove(&mut self, msg_id: usize) -> Option<PendingRequest<N>> {
+        self.0.remove(&msg_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.0.values().map(|request| request.deadline).min()
+    }
+
+    /// Reschedule every request that is due and can be retried, and remove the rest.
+    pub fn expire(&mut self, now: Instant) -> Vec<Expired<N>> {
+        let due: Vec<usize> = self
+            .0
+            .iter()
+            .filter(|(_, request)| request.deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        due.into_iter()
+            .filter_map(|msg_id| {
+                let request = self.0.get_mut(&msg_id)?;
+                if request.policy.can_retry(request.attempt) {
+                    request.attempt += 1;
+                    request.deadline = now + request.policy.timeout(request.attempt);
+                    let dest = request.dest.clone();
+                    let payload = request.payload.clone();
+                    Some(Expired::Retry {
+                        msg_id,
+                        dest,
+                        payload,
+                    })
+                } else {
+                    let request = self.0.remove(&msg_id)?;
+                    Some(Expired::GiveUp {
+                        msg_id,
+                        dest: request.dest,
+                        callback: request.callback,
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending_request(policy: RetryPolicy) -> PendingRequest<()> {
+        let dest = NodeId::new(1);
+        let callback: Callback<()> = Box::new(|_, _, _| {});
+        PendingRequest::new(dest, Value::Null, policy, callback)
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy::new(Duration::from_millis(100))
+            .with_retries(None)
+            .with_backoff(2.0, Duration::from_millis(300));
+        assert_eq!(policy.timeout(0), Duration::from_millis(100));
+        assert_eq!(policy.timeout(1), Duration::from_millis(200));
+        assert_eq!(policy.timeout(2), Duration::from_millis(300));
+        assert_eq!(policy.timeout(10), Duration::from_millis(300));
+        assert_eq!(policy.timeout(10_000), Duration::from_millis(300));
+        assert_eq!(policy.timeout(u32::MAX), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_expire_retries_then_gives_up() {
+        let policy = RetryPolicy::new(Duration::ZERO).with_retries(1);
+        let mut pending_requests = PendingRequests::default();
+        pending_requests.insert(0, pending_request(policy));
+
+        let expired = pending_requests.expire(Instant::now());
+        assert!(matches!(expired[..], [Expired::Retry { msg_id: 0, .. }]));
+        assert_eq!(pending_requests.len(), 1);
+
+        let expired = pending_requests.expire(Instant::now());
+        assert!(matches!(expired[..], [Expired::GiveUp { msg_id: 0, .. }]));
+        assert!(pending_requests.is_empty());
+    }
+}
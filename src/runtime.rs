@@ -0,0 +1,381 @@
+use std::{
+    collections::HashSet,
+    io,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Instant,
+};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    config::{Cli, LogConfig, LogFormat},
+    context::Context,
+    error::{self, Error},
+    message::{Message, NodeId},
+    node::Node,
+};
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+enum InitPayload {
+    Init {
+        node_id: NodeId,
+        node_ids: HashSet<NodeId>,
+    },
+    InitOk,
+}
+
+enum Event {
+    Request(String),
+    Timer,
+    /// A line that could not be read, which has been logged.
+    Skipped,
+    Closed,
+}
+
+/// Run `N` against Maelstrom over stdin and stdout until stdin is closed.
+///
+/// Stdin is read on its own thread so that [`Node::tick`] keeps firing on schedule while no
+/// requests are arriving.
+pub fn run<N: Node>() -> color_eyre::Result<()> {
+    let cli = Cli::<N::Config>::parse();
+    initialise_tracing(&cli.log)?;
+
+    let lines = spawn_stdin_reader();
+    info!("Got stdin");
+
+    let Some(mut ctx) = initialise(lines.iter())? else {
+        return Ok(());
+    };
+    let mut node = N::init(cli.node, &mut ctx);
+
+    let mut next_tick = schedule_tick(&node);
+    loop {
+        let deadline = earliest(next_tick, ctx.next_deadline());
+        match next_event(&lines, deadline)? {
+            Event::Request(line) => dispatch(&mut node, &mut ctx, &line),
+            Event::Timer | Event::Skipped => {}
+            Event::Closed => break,
+        }
+        let now = Instant::now();
+        for (callback, error) in ctx.expire_rpcs(now) {
+            callback(&mut node, Err(error), &mut ctx);
+        }
+        if next_tick.is_some_and(|deadline| now >= deadline) {
+            node.tick(&mut ctx);
+            next_tick = schedule_tick(&node);
+        }
+    }
+    Ok(())
+}
+
+/// Hand a reply to the callback waiting for it, or anything else to [`Node::handle`].
+///
+/// Lines that are not Maelstrom messages are logged and skipped. Messages whose payload `N` does
+/// not understand are kept as raw JSON, logged, and answered with an error.
+pub fn dispatch<N: Node>(node: &mut N, ctx: &mut Context<N>, line: &str) {
+    let message = match serde_json::from_str::<Message<Value>>(line) {
+        Ok(message) => message,
+        Err(error) => {
+            error!(target: "Malformed message", error = %error, line);
+            return;
+        }
+    };
+    info!(target: "Received message", message = ?message);
+    let Message { src, dest, body } = message;
+    if let Some(callback) = body
+        .in_reply_to
+        .and_then(|msg_id| ctx.take_callback(msg_id))
+    {
+        let reply = into_reply(body.payload);
+        callback(node, reply, ctx);
+        return;
+    }
+    match N::Payload::deserialize(&body.payload) {
+        Ok(payload) => {
+            let request = Message::new(src, dest, body.msg_id, body.in_reply_to, payload);
+            node.handle(request, ctx);
+        }
+        Err(error) => {
+            error!(target: "Unsupported message", error = %error, line);
+            reject_unsupported_payload(ctx, src, body.msg_id, &body.payload, error);
+        }
+    }
+}
+
+fn reject_unsupported_payload<N>(
+    ctx: &mut Context<N>,
+    src: NodeId,
+    msg_id: Option<usize>,
+    payload: &Value,
+    error: serde_json::Error,
+) {
+    let error = match payload.get("type").and_then(Value::as_str) {
+        // Answering an error with an error could bounce between two nodes forever.
+        Some("error") => return,
+        Some(message_type) => {
+            Error::NotSupported(format!("cannot handle `{message_type}` messages: {error}"))
+        }
+        None => Error::MalformedRequest(format!("message has no `type`: {error}")),
+    };
+    // Without a `msg_id` the sender has no way to match the error to its request.
+    if msg_id.is_some() {
+        ctx.reply_error(src, msg_id, error);
+    }
+}
+
+fn into_reply(payload: Value) -> Result<Value, Error> {
+    if payload.get("type").and_then(Value::as_str) != Some("error") {
+        return Ok(payload);
+    }
+    match serde_json::from_value::<error::Payload>(payload) {
+        Ok(payload) => Err(Error::from(payload)),
+        Err(error) => Err(Error::MalformedRequest(error.to_string())),
+    }
+}
+
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn spawn_stdin_reader() -> Receiver<io::Result<String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn schedule_tick(node: &impl Node) -> Option<Instant> {
+    node.tick_interval()
+        .map(|interval| Instant::now() + interval)
+}
+
+/// Wait for the next line from stdin, giving up once `deadline` has passed.
+///
+/// Lines that are not valid UTF-8 are skipped; only other read failures are returned.
+fn next_event(
+    lines: &Receiver<io::Result<String>>,
+    deadline: Option<Instant>,
+) -> io::Result<Event> {
+    let line = match deadline {
+        Some(deadline) => {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(Event::Timer),
+                Err(RecvTimeoutError::Disconnected) => return Ok(Event::Closed),
+            }
+        }
+        None => match lines.recv() {
+            Ok(line) => line,
+            Err(_) => return Ok(Event::Closed),
+        },
+    };
+    Ok(skip_invalid_data(line)?.map_or(Event::Skipped, Event::Request))
+}
+
+/// Log and drop a line that is not valid UTF-8, which stdin still reads past.
+fn skip_invalid_data(line: io::Result<String>) -> io::Result<Option<String>> {
+    match line {
+        Ok(line) => Ok(Some(line)),
+        Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+            error!(target: "Malformed message", error = %error);
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn initialise<N>(
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> io::Result<Option<Context<N>>> {
+    for line in lines {
+        let Some(line) = skip_invalid_data(line)? else {
+            continue;
+        };
+        let Ok(request) = serde_json::from_str::<Message<InitPayload>>(&line) else {
+            reject_uninitialised_request(&line);
+            continue;
+        };
+        info!(target: "Received message", message = ?request);
+        match request.body.payload {
+            InitPayload::Init { node_id, node_ids } => {
+                let mut ctx = Context::new(node_id, node_ids, 0);
+                ctx.reply(request.src, request.body.msg_id, InitPayload::InitOk);
+                return Ok(Some(ctx));
+            }
+            InitPayload::InitOk => reject_uninitialised_request(&line),
+        }
+    }
+    Ok(None)
+}
+
+fn reject_uninitialised_request(line: &str) {
+    error!(target: "invalid payload", node_type = "Uninitialised", line);
+    if let Some(response) = uninitialised_response(line) {
+        response.send();
+    }
+}
+
+/// The error to answer `line` with before `init`, unless it is an error itself or has no
+/// `msg_id` to reply to, for the same reasons as [`reject_unsupported_payload`].
+fn uninitialised_response(line: &str) -> Option<Message<error::Payload>> {
+    let request = serde_json::from_str::<Message<Value>>(line).ok()?;
+    if request.body.payload.get("type").and_then(Value::as_str) == Some("error") {
+        return None;
+    }
+    let msg_id = request.body.msg_id?;
+    let error = Error::TemporarilyUnavailable("node has not been initialised".into());
+    let payload = error::Payload::from(error);
+    let response = Message::new(request.dest, request.src, None, msg_id, payload);
+    Some(response)
+}
+
+fn initialise_tracing(config: &LogConfig) -> color_eyre::Result<()> {
+    let filter = EnvFilter::try_new(&config.log_filter)?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(false);
+    match config.log_format {
+        LogFormat::Full => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::NoConfig;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case", tag = "type")]
+    enum EchoPayload {
+        Echo { echo: String },
+        EchoOk { echo: String },
+    }
+
+    struct EchoNode;
+
+    impl Node for EchoNode {
+        type Payload = EchoPayload;
+        type Config = NoConfig;
+
+        fn init(_: NoConfig, _: &mut Context<Self>) -> Self {
+            Self
+        }
+
+        fn handle(&mut self, request: Message<EchoPayload>, ctx: &mut Context<Self>) {
+            if let EchoPayload::Echo { echo } = request.body.payload {
+                ctx.reply(
+                    request.src,
+                    request.body.msg_id,
+                    EchoPayload::EchoOk { echo },
+                );
+            }
+        }
+    }
+
+    fn echo_node() -> (EchoNode, Context<EchoNode>) {
+        let node_id = NodeId::new(1);
+        let node_ids = HashSet::from([node_id.clone()]);
+        let mut ctx = Context::new(node_id, node_ids, 0).with_outbox();
+        (EchoNode::init(NoConfig {}, &mut ctx), ctx)
+    }
+
+    fn error_code(message: &Message<Value>) -> Option<u64> {
+        message.body.payload.get("code").and_then(Value::as_u64)
+    }
+
+    #[test]
+    fn test_dispatch_answers_unknown_types_with_not_supported() {
+        let (mut node, mut ctx) = echo_node();
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"frobnicate","msg_id":3}}"#;
+        dispatch(&mut node, &mut ctx, line);
+        let sent = ctx.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, NodeId::Client(1));
+        assert_eq!(sent[0].body.in_reply_to, Some(3));
+        assert_eq!(error_code(&sent[0]), Some(10));
+    }
+
+    #[test]
+    fn test_dispatch_answers_messages_without_type_with_malformed_request() {
+        let (mut node, mut ctx) = echo_node();
+        let line = r#"{"src":"c1","dest":"n1","body":{"msg_id":3}}"#;
+        dispatch(&mut node, &mut ctx, line);
+        let sent = ctx.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(error_code(&sent[0]), Some(12));
+    }
+
+    #[test]
+    fn test_dispatch_does_not_answer_errors_or_messages_without_msg_id() {
+        let (mut node, mut ctx) = echo_node();
+        let error = r#"{"src":"n2","dest":"n1","body":{"type":"error","in_reply_to":9,"code":11,"text":"no"}}"#;
+        dispatch(&mut node, &mut ctx, error);
+        let unknown = r#"{"src":"n2","dest":"n1","body":{"type":"frobnicate"}}"#;
+        dispatch(&mut node, &mut ctx, unknown);
+        assert!(ctx.take_sent().is_empty());
+    }
+
+    #[test]
+    fn test_dispatch_skips_malformed_lines_and_carries_on() {
+        let (mut node, mut ctx) = echo_node();
+        dispatch(&mut node, &mut ctx, "{not json");
+        assert!(ctx.take_sent().is_empty());
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4,"echo":"hi"}}"#;
+        dispatch(&mut node, &mut ctx, line);
+        let sent = ctx.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body.payload["echo"], "hi");
+    }
+
+    #[test]
+    fn test_lines_that_are_not_utf8_are_skipped() {
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "not UTF-8");
+        assert!(matches!(skip_invalid_data(Err(invalid)), Ok(None)));
+        let broken = io::Error::new(io::ErrorKind::BrokenPipe, "closed");
+        assert!(skip_invalid_data(Err(broken)).is_err());
+
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "not UTF-8");
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+        let lines = [Err(invalid), Ok(init.to_string())];
+        let ctx = initialise::<EchoNode>(lines.into_iter()).expect("Reading carries on");
+        assert!(ctx.is_some());
+    }
+
+    #[test]
+    fn test_uninitialised_requests_are_answered_with_an_error() {
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4,"echo":"hi"}}"#;
+        let response = uninitialised_response(line).expect("A request is answered");
+        assert_eq!(response.dest, NodeId::Client(1));
+        assert_eq!(response.body.in_reply_to, Some(4));
+        let error = Error::from(response.body.payload);
+        assert!(matches!(error, Error::TemporarilyUnavailable(_)));
+    }
+
+    #[test]
+    fn test_uninitialised_errors_and_messages_without_msg_id_are_not_answered() {
+        let error = r#"{"src":"n2","dest":"n1","body":{"type":"error","msg_id":4,"in_reply_to":1,"code":11,"text":"no"}}"#;
+        assert!(uninitialised_response(error).is_none());
+        let gossip = r#"{"src":"n2","dest":"n1","body":{"type":"gossip","ids_to_see":[]}}"#;
+        assert!(uninitialised_response(gossip).is_none());
+    }
+}